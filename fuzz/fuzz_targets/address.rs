@@ -0,0 +1,14 @@
+#![no_main]
+
+use corona_fuzz::core::{Address, MessageId, Via};
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+
+fuzz_target!(|s: &str| {
+    if let Ok(a) = Address::from_str(s) {
+        assert_eq!(Address::from_str(&a.to_string()), Ok(a));
+    }
+
+    let _ = MessageId::from_str(s);
+    let _ = Via::from_str(s);
+});
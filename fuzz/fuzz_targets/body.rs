@@ -0,0 +1,14 @@
+#![no_main]
+
+use corona_fuzz::{core, ftn::Package, pkt_with_text};
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+// zeros would end the text early
+fuzz_target!(|data: &[u8]| {
+    let text: Vec<u8> = data.iter().copied().filter(|&b| b != 0).collect();
+    let mut pkg = Package::read(Cursor::new(pkt_with_text(&text))).unwrap();
+    let m = pkg.next().unwrap().unwrap();
+
+    let _ = core::message_from(&pkg.header, m, |_| true);
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use corona_fuzz::ftn::Bundle;
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut bundle) = Bundle::read(Cursor::new(data)) {
+        for i in 0..bundle.file_count() {
+            if let Ok(Some(pkg)) = bundle.package(i) {
+                pkg.with_max_message_size(1 << 20).for_each(drop);
+            }
+        }
+    }
+});
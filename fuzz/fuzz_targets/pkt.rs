@@ -0,0 +1,13 @@
+#![no_main]
+
+use corona_fuzz::{core, ftn::Package};
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut pkg) = Package::read(Cursor::new(data)).map(|p| p.with_max_message_size(1 << 20)) {
+        while let Some(Ok(m)) = pkg.next() {
+            let _ = core::message_from(&pkg.header, m, |_| true);
+        }
+    }
+});
@@ -0,0 +1,28 @@
+//! Helpers for the fuzz targets.
+//! Run with `cargo +nightly fuzz run <target> ../tests/corpus`.
+
+pub use corona::{core, ftn};
+
+/// Type 2+ packet carrying a single message with `text`
+pub fn pkt_with_text(text: &[u8]) -> Vec<u8> {
+    let mut data = vec![0u8; 58];
+
+    data[0] = 100; // orig node
+    data[2] = 1; // dest node
+    data[4..6].copy_from_slice(&2020u16.to_le_bytes());
+    data[8] = 1; // day
+    data[18] = 2; // packet version
+    data[20..22].copy_from_slice(&5020u16.to_le_bytes());
+    data[22..24].copy_from_slice(&5020u16.to_le_bytes());
+    data[34] = 2; // qm orig zone
+    data[36] = 2; // qm dest zone
+    data[40..42].copy_from_slice(&1u16.to_be_bytes()); // capability word copy
+    data[44..46].copy_from_slice(&1u16.to_le_bytes()); // capability word
+
+    data.extend_from_slice(&[2, 0, 100, 0, 1, 0, 0x9c, 0x13, 0x9c, 0x13, 0, 0, 0, 0]);
+    data.extend_from_slice(b"01 Jan 20  00:00:00\0All\0Fuzzer\0Subject\0");
+    data.extend_from_slice(text);
+    data.extend_from_slice(&[0, 0, 0]);
+
+    data
+}
@@ -0,0 +1,218 @@
+use chrono::{SecondsFormat, Utc};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+
+/// Extension of the record next to each bad file
+const RECORD_EXT: &str = "toml";
+
+/// Why a file has been moved to bad mail
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Record {
+    /// Name the file had in inbound
+    pub file: String,
+    /// Inbound directory it came from
+    pub inbound: String,
+    pub error: String,
+    /// RFC 3339, UTC
+    pub time: String,
+    /// Version of corona which failed to toss it
+    pub version: String,
+}
+
+/// Directory of files which failed to toss, each with a [`Record`] in `<name>.toml` next to it
+///
+/// ```
+/// use corona::badmail::BadMail;
+///
+/// let dir = std::env::temp_dir().join(format!("corona-doc-badmail-{}", std::process::id()));
+/// let bad = BadMail::new(&dir);
+///
+/// assert!(bad.list()?.is_empty());
+/// # Ok::<(), corona::Error>(())
+/// ```
+pub struct BadMail {
+    dir: PathBuf,
+}
+
+impl BadMail {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf() }
+    }
+
+    /// Moves `path` from the `inbound` directory here and records `error`.
+    /// A file which is here already keeps its name and record, only the error is updated.
+    pub(crate) fn put(&self, path: &Path, inbound: &str, error: &dyn fmt::Display) -> Result<PathBuf> {
+        let name = path
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+
+        let (bad, record) = if path.parent() == Some(&self.dir) {
+            let record = match self.record(path) {
+                Ok(old) => Record::new(&old.file, &old.inbound, error),
+                Err(_) => Record::new(&name, inbound, error),
+            };
+            (path.to_path_buf(), record)
+        } else {
+            fs::create_dir_all(&self.dir)?;
+            let bad = self.free_name(&name);
+            move_file(path, &bad)?;
+            (bad, Record::new(&name, inbound, error))
+        };
+
+        let text = toml::to_string(&record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(record_path(&bad), text)?;
+
+        Ok(bad)
+    }
+
+    /// Bad files with their records, oldest first. Files without a readable record are left out.
+    pub fn list(&self) -> Result<Vec<(PathBuf, Record)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut bad: Vec<_> = fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|x| x == RECORD_EXT))
+            .map(|p| p.with_extension(""))
+            .filter(|p| p.is_file())
+            .filter_map(|p| self.record(&p).ok().map(|r| (p, r)))
+            .collect();
+
+        bad.sort_by(|(p, a), (q, b)| (&a.time, p).cmp(&(&b.time, q)));
+
+        Ok(bad)
+    }
+
+    /// Drops the record of a bad file which has been tossed
+    pub(crate) fn clear(&self, path: &Path) -> Result<()> {
+        match fs::remove_file(record_path(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn record(&self, path: &Path) -> Result<Record> {
+        let text = fs::read_to_string(record_path(path))?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+
+    /// `name`, or `<n>-name` if there is a bad file of that name already
+    fn free_name(&self, name: &str) -> PathBuf {
+        (1..)
+            .map(|n| match n {
+                1 => self.dir.join(name),
+                _ => self.dir.join(format!("{n}-{name}")),
+            })
+            .find(|p| !p.exists() && !record_path(p).exists())
+            .unwrap()
+    }
+}
+
+impl Record {
+    fn new(file: &str, inbound: &str, error: &dyn fmt::Display) -> Self {
+        Self {
+            file: file.to_string(),
+            inbound: inbound.to_string(),
+            error: error.to_string(),
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// Renames `from` to `to`, or copies it if bad mail is on another file system
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_file(from, to),
+        res => res,
+    }
+}
+
+/// Copies `from` to `to` and removes `from` once the copy is on disk
+fn copy_file(from: &Path, to: &Path) -> io::Result<()> {
+    let res = fs::copy(from, to).and_then(|_| File::open(to)?.sync_all());
+
+    if let Err(e) = res {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+
+    fs::remove_file(from)
+}
+
+fn record_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(RECORD_EXT);
+    name.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{copy_file, BadMail};
+    use crate::error::Error;
+    use std::fs;
+    use std::io;
+
+    #[test]
+    fn put_list_and_clear() {
+        let root = std::env::temp_dir().join(format!("corona-badmail-{}", std::process::id()));
+        let inbound = root.join("inbound");
+        let bad = BadMail::new(&root.join("badmail"));
+        fs::create_dir_all(&inbound).unwrap();
+
+        let error = Error::Io(io::ErrorKind::InvalidData.into());
+
+        for _ in 0..2 {
+            fs::write(inbound.join("00000001.pkt"), b"packet").unwrap();
+            bad.put(&inbound.join("00000001.pkt"), &inbound.to_string_lossy(), &error)
+                .unwrap();
+        }
+
+        let list = bad.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list
+            .iter()
+            .all(|(_, r)| r.file == "00000001.pkt" && r.inbound == inbound.to_string_lossy()));
+        assert_ne!(list[0].0, list[1].0);
+
+        // failed again: same file, same origin
+        let again = bad.put(&list[1].0, "elsewhere", &Error::Locked(None)).unwrap();
+        assert_eq!(again, list[1].0);
+        let record = bad.record(&again).unwrap();
+        assert_eq!(record.inbound, inbound.to_string_lossy());
+        assert_eq!(record.error, Error::Locked(None).to_string());
+
+        fs::remove_file(&again).unwrap();
+        bad.clear(&again).unwrap();
+        assert_eq!(bad.list().unwrap().len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn move_across_file_systems() {
+        let root = std::env::temp_dir().join(format!("corona-badmail-copy-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let (from, to) = (root.join("00000001.pkt"), root.join("bad.pkt"));
+
+        fs::write(&from, b"packet").unwrap();
+        copy_file(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).unwrap(), b"packet");
+
+        // nothing is left behind when the copy fails
+        assert!(copy_file(&from, &root.join("other.pkt")).is_err());
+        assert!(!root.join("other.pkt").exists());
+        assert!(to.exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,25 +1,489 @@
+use log::LevelFilter;
+use serde::{Deserialize as _, Deserializer};
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fmt, io};
 
-#[derive(Debug, Deserialize)]
+use crate::core::{self, Address, AddressPattern};
+
+const DEFAULT_MAX_MESSAGE_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_LOCK_FILE: &str = "corona.lock";
+const DEFAULT_BAD_MAIL_DIR: &str = "badmail";
+const DEFAULT_SETTLE_SECONDS: u64 = 10;
+const DEFAULT_MIN_FREE_MB: u64 = 16;
+const DEFAULT_LOG_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_LOG_KEEP: usize = 5;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    /// Not a valid TOML or unknown value types
+    Syntax(toml::de::Error),
+    /// Values which parse but make no sense
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Syntax(e) => e.fmt(f),
+            Self::Invalid(s) => s.fmt(f),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Syntax(e) => Some(e),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+/// Settings from `corona.toml`, every section is optional.
+/// `inbound` is a single table or an array of them, one per inbound directory.
+///
+/// ```
+/// let cfg: corona::cfg::Config = r#"
+///     [[inbound]]
+///     path = "/var/spool/ftn/inbound"
+///     allow = ["2:5020/*"]
+///
+///     [[inbound]]
+///     path = "/var/spool/ftn/insecure"
+///     trust = "insecure"
+///     areas = ["NODELIST.*"]
+/// "#
+/// .parse()?;
+///
+/// let [secure, insecure] = cfg.inbounds() else { panic!() };
+///
+/// assert!(secure.allowed(&"2:5020/100".parse()?));
+/// assert!(!secure.allowed(&"2:5030/100".parse()?));
+/// assert!(insecure.accepts(&corona::core::Area::Echomail("NODELIST.R50".to_string())));
+/// assert!(!insecure.accepts(&corona::core::Area::Echomail("SU.GENERAL".to_string())));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Default, Deserialize)]
 pub struct Config {
-    pub inbound: Option<Inbound>,
+    pub node: Option<Node>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub inbound: Option<Vec<Inbound>>,
+    pub outbound: Option<Outbound>,
     pub msgbase: Option<Msgbase>,
+    pub area: Option<Vec<Area>>,
+    pub domain: Option<Vec<Domain>>,
+    pub lock: Option<Lock>,
+    pub link: Option<Vec<Link>>,
+    pub log: Option<Log>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
+pub struct Node {
+    /// Our addresses (AKAs), the first one is the main
+    pub address: Vec<String>,
+    /// Points of our nodes, netmail to any other point is bounced
+    pub points: Option<Vec<u16>>,
+    /// Nodes netmail can be routed to besides links, e.g. `2:5020/*`. Netmail to other nodes is bounced.
+    /// Anywhere by default.
+    pub routes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct Inbound {
     pub path: Option<String>,
+    /// How far packets from this directory are trusted, `secure` by default
+    pub trust: Option<Trust>,
+    /// Echo areas accepted from an `insecure` inbound, e.g. `NODELIST.*`, none by default.
+    /// Other echomail goes to quarantine.
+    pub areas: Option<Vec<String>>,
+    /// Larger messages make the whole packet bad, 16 MiB by default
+    pub max_message_size: Option<u64>,
+    /// Toss what can be read from damaged packets, the packets still go to bad mail afterwards
+    pub lenient: Option<bool>,
+    /// Links whose packets are tossed, e.g. `2:5020/*`, the rest go to bad mail. Anyone by default.
+    pub allow: Option<Vec<String>>,
+    /// Messages posted earlier than this many days ago go to quarantine
+    pub max_age_days: Option<u32>,
+    /// Messages posted later than this many minutes from now go to quarantine
+    pub max_future_minutes: Option<u32>,
+    /// Files modified less than this many seconds ago are left for the next run, 10 by default
+    pub settle_seconds: Option<u64>,
+    /// Nothing is tossed while this file exists, e.g. a busy flag of the mailer
+    pub busy_flag: Option<String>,
+    /// Free space needed on the filesystem, 16 MiB by default
+    pub min_free_mb: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Who has put packets into an inbound directory
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trust {
+    /// Sessions where the mailer has verified the link's password
+    #[default]
+    Secure,
+    /// Sessions with unlisted callers
+    Insecure,
+    /// Our own tools, packet passwords aren't checked
+    Local,
+}
+
+/// Outbound of the mailer, only checked for free space so far
+#[derive(Debug, Default, Deserialize)]
+pub struct Outbound {
+    pub path: Option<String>,
+    /// Free space needed on the filesystem, 16 MiB by default
+    pub min_free_mb: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct Msgbase {
     pub path: Option<String>,
+    /// Default for areas which don't set `keep_raw` explicitly
+    pub keep_raw: Option<bool>,
+    /// Directory for files which fail to toss, `badmail` next to the message bases by default
+    pub bad_mail: Option<String>,
+    /// Free space needed on the filesystem, 16 MiB by default
+    pub min_free_mb: Option<u64>,
+}
+
+/// Semaphore which keeps mail processing runs from overlapping
+#[derive(Debug, Default, Deserialize)]
+pub struct Lock {
+    /// `corona.lock` next to the message bases by default
+    pub path: Option<String>,
+    /// How long to wait for another run to finish, a busy lock fails at once by default
+    pub wait_seconds: Option<u64>,
+}
+
+/// Node we exchange mail with
+#[derive(Debug, Deserialize)]
+pub struct Link {
+    pub address: String,
+    /// Packets from the link must carry it, unless they come from a `local` inbound
+    pub password: Option<String>,
+}
+
+/// Diagnostics, see [`crate::logging`]. Levels are `off`, `error`, `warn`, `info`, `debug` or `trace`.
+#[derive(Debug, Default, Deserialize)]
+pub struct Log {
+    /// Level of the console, `info` by default. `warn` keeps cron quiet.
+    pub console: Option<String>,
+    /// Log file, none by default
+    pub file: Option<String>,
+    /// Level of the log file, `info` by default
+    pub level: Option<String>,
+    /// Levels of the log file by module, e.g. `"corona::store" = "debug"`. The console has
+    /// the `console` level for all modules.
+    pub targets: Option<HashMap<String, String>>,
+    pub format: Option<LogFormat>,
+    /// The file is rotated once it grows larger, 10 MiB by default
+    pub max_size_mb: Option<u64>,
+    /// Rotated files kept as `<file>.1` (the newest) and on, 5 by default
+    pub keep: Option<usize>,
+}
+
+/// Line format of the log file
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Date, level and module of each line
+    #[default]
+    Plain,
+    /// `corona.log` lines like those of binkd and other FTN software
+    Ftn,
+}
+
+/// FTN domain of `zones`, given to addresses which come without one
+#[derive(Debug, Deserialize)]
+pub struct Domain {
+    pub name: String,
+    pub zones: Vec<u16>,
+}
+
+/// Per-area settings, `name` is an echo tag or `netmail`
+#[derive(Debug, Deserialize)]
+pub struct Area {
+    pub name: String,
+    /// Store the original message text (compressed) next to the parsed one
+    pub keep_raw: Option<bool>,
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cfg: Config = toml::from_str(s).map_err(ConfigError::Syntax)?;
+
+        if let Some(node) = &cfg.node {
+            for a in &node.address {
+                Address::from_str(a).map_err(|e| ConfigError::Invalid(format!("node address \"{a}\": {e}")))?;
+            }
+
+            for p in node.routes.iter().flatten() {
+                AddressPattern::from_str(p).map_err(|e| ConfigError::Invalid(format!("route \"{p}\": {e}")))?;
+            }
+        }
+
+        for p in cfg.inbounds().iter().flat_map(|i| i.allow.iter().flatten()) {
+            AddressPattern::from_str(p).map_err(|e| ConfigError::Invalid(format!("allowed link \"{p}\": {e}")))?;
+        }
+
+        if let Some(log) = &cfg.log {
+            let levels = [&log.console, &log.level].into_iter().flatten();
+
+            for l in levels.chain(log.targets.iter().flat_map(|t| t.values())) {
+                LevelFilter::from_str(l).map_err(|_| ConfigError::Invalid(format!("log level \"{l}\"")))?;
+            }
+        }
+
+        for l in cfg.link.iter().flatten() {
+            let a = &l.address;
+            Address::from_str(a).map_err(|e| ConfigError::Invalid(format!("link address \"{a}\": {e}")))?;
+        }
+
+        Ok(cfg)
+    }
 }
 
 impl Config {
-    pub fn new(path: &Path) -> Result<Config, Box<dyn Error>> {
-        Ok(toml::from_slice(&std::fs::read(path)?)?)
+    pub fn new(path: &Path) -> Result<Config, ConfigError> {
+        std::fs::read_to_string(path).map_err(ConfigError::Io)?.parse()
+    }
+
+    /// Our addresses, the main one goes first
+    pub fn akas(&self) -> Vec<Address> {
+        self.node.as_ref().map_or(Vec::new(), |n| {
+            n.address
+                .iter()
+                .filter_map(|a| Address::from_str(a).ok())
+                .map(|a| self.qualify(&a))
+                .collect()
+        })
+    }
+
+    /// Addresses of the links
+    pub fn links(&self) -> Vec<Address> {
+        self.link
+            .iter()
+            .flatten()
+            .filter_map(|l| Address::from_str(&l.address).ok())
+            .map(|a| self.qualify(&a))
+            .collect()
+    }
+
+    /// Nodes netmail can be routed to besides links, `None` if it can be routed anywhere
+    pub fn routes(&self) -> Option<Vec<AddressPattern>> {
+        let routes = self.node.as_ref()?.routes.as_ref()?;
+
+        Some(routes.iter().filter_map(|p| AddressPattern::from_str(p).ok()).collect())
+    }
+
+    /// `a` with the domain of its zone if it has none
+    pub fn qualify(&self, a: &Address) -> Address {
+        let domain = self.domain.iter().flatten().find(|d| d.zones.contains(&a.zone));
+
+        match (&a.domain, domain) {
+            (None, Some(d)) => Address::new_5d(a.zone, a.net, a.node, a.point, &d.name),
+            _ => a.clone(),
+        }
+    }
+
+    pub fn inbounds(&self) -> &[Inbound] {
+        self.inbound.as_deref().unwrap_or_default()
+    }
+
+    /// Password packets from `link` must carry
+    pub fn password(&self, link: &Address) -> Option<&str> {
+        self.link
+            .iter()
+            .flatten()
+            .find(|l| Address::from_str(&l.address).is_ok_and(|a| self.qualify(&a) == *link))?
+            .password
+            .as_deref()
+    }
+
+    pub fn area(&self, area: &crate::core::Area) -> Option<&Area> {
+        let name = match area {
+            crate::core::Area::Netmail => "netmail",
+            crate::core::Area::Echomail(name) => name,
+        };
+
+        self.area.as_ref()?.iter().find(|a| a.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Directories with the free space in bytes their filesystems need
+    pub fn min_free_space(&self) -> Vec<(&Path, u64)> {
+        let dirs = [
+            self.msgbase.as_ref().map(|m| (&m.path, m.min_free_mb)),
+            self.outbound.as_ref().map(|o| (&o.path, o.min_free_mb)),
+        ];
+
+        dirs.into_iter()
+            .flatten()
+            .chain(self.inbounds().iter().map(|i| (&i.path, i.min_free_mb)))
+            .filter_map(|(path, mb)| {
+                Some((
+                    Path::new(path.as_ref()?),
+                    mb.unwrap_or(DEFAULT_MIN_FREE_MB).saturating_mul(1 << 20),
+                ))
+            })
+            .collect()
+    }
+
+    pub fn lock_path(&self) -> Option<PathBuf> {
+        match self.lock.as_ref().and_then(|l| l.path.as_ref()) {
+            Some(path) => Some(path.into()),
+            None => Some(Path::new(self.msgbase.as_ref()?.path.as_ref()?).join(DEFAULT_LOCK_FILE)),
+        }
+    }
+
+    /// Directory of the message bases, an error if `[msgbase]` has no path
+    pub fn msgbase_path(&self) -> Result<&Path, ConfigError> {
+        let path = self
+            .msgbase
+            .as_ref()
+            .and_then(|m| m.path.as_ref())
+            .ok_or_else(|| ConfigError::Invalid("msgbase path is not set".to_string()))?;
+
+        Ok(Path::new(path))
+    }
+
+    pub fn bad_mail_path(&self) -> Option<PathBuf> {
+        let msgbase = self.msgbase.as_ref()?;
+
+        match &msgbase.bad_mail {
+            Some(path) => Some(path.into()),
+            None => Some(Path::new(msgbase.path.as_ref()?).join(DEFAULT_BAD_MAIL_DIR)),
+        }
+    }
+
+    pub fn console_level(&self) -> LevelFilter {
+        self.log.as_ref().map_or(LevelFilter::Info, |l| l.console_level())
+    }
+
+    pub fn lock_wait(&self) -> Duration {
+        Duration::from_secs(self.lock.as_ref().and_then(|l| l.wait_seconds).unwrap_or(0))
+    }
+
+    pub fn keep_raw(&self, area: &crate::core::Area) -> bool {
+        self.area(area)
+            .and_then(|a| a.keep_raw)
+            .or_else(|| self.msgbase.as_ref().and_then(|m| m.keep_raw))
+            .unwrap_or(false)
+    }
+}
+
+impl Inbound {
+    /// Whether packets from `link` are tossed
+    pub fn allowed(&self, link: &Address) -> bool {
+        match &self.allow {
+            Some(allow) => allow
+                .iter()
+                .filter_map(|p| AddressPattern::from_str(p).ok())
+                .any(|p| p.matches(link)),
+            None => true,
+        }
+    }
+
+    pub fn trust(&self) -> Trust {
+        self.trust.unwrap_or_default()
+    }
+
+    /// Whether messages of `area` are tossed from this inbound, all but echomail are
+    pub fn accepts(&self, area: &core::Area) -> bool {
+        match (self.trust(), area) {
+            (Trust::Insecure, core::Area::Echomail(name)) => {
+                self.areas.iter().flatten().any(|pattern| area_matches(pattern, name))
+            }
+            _ => true,
+        }
+    }
+
+    pub fn max_message_size(&self) -> u64 {
+        self.max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE)
     }
+
+    pub fn lenient(&self) -> bool {
+        self.lenient.unwrap_or(false)
+    }
+
+    pub fn settle_time(&self) -> Duration {
+        Duration::from_secs(self.settle_seconds.unwrap_or(DEFAULT_SETTLE_SECONDS))
+    }
+
+    pub fn busy_flag(&self) -> Option<&Path> {
+        self.busy_flag.as_ref().map(Path::new)
+    }
+
+    pub fn max_age(&self) -> Option<chrono::Duration> {
+        Some(chrono::Duration::days(self.max_age_days?.into()))
+    }
+
+    pub fn max_future_skew(&self) -> Option<chrono::Duration> {
+        Some(chrono::Duration::minutes(self.max_future_minutes?.into()))
+    }
+}
+
+/// Echo tag `pattern`, where a trailing `*` matches any suffix
+fn area_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.get(..prefix.len()).is_some_and(|n| n.eq_ignore_ascii_case(prefix)),
+        None => pattern.eq_ignore_ascii_case(name),
+    }
+}
+
+impl Log {
+    pub fn console_level(&self) -> LevelFilter {
+        level(&self.console)
+    }
+
+    pub fn file_level(&self) -> LevelFilter {
+        level(&self.level)
+    }
+
+    pub fn targets(&self) -> impl Iterator<Item = (String, LevelFilter)> + '_ {
+        self.targets
+            .iter()
+            .flatten()
+            .map(|(target, l)| (target.clone(), LevelFilter::from_str(l).unwrap_or(LevelFilter::Info)))
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mb
+            .unwrap_or(DEFAULT_LOG_MAX_SIZE_MB)
+            .saturating_mul(1 << 20)
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(DEFAULT_LOG_KEEP)
+    }
+}
+
+fn level(l: &Option<String>) -> LevelFilter {
+    l.as_deref()
+        .and_then(|l| LevelFilter::from_str(l).ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
+/// `[inbound]` of older configs or `[[inbound]]`
+fn one_or_many<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<Inbound>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Inbound),
+        Many(Vec<Inbound>),
+    }
+
+    Ok(Option::<OneOrMany>::deserialize(d)?.map(|i| match i {
+        OneOrMany::One(i) => vec![i],
+        OneOrMany::Many(i) => i,
+    }))
 }
@@ -1,8 +1,53 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version, about)]
 pub enum Args {
     /// Toss inbound mail
-    Toss,
+    Toss {
+        /// Also write the run statistics as JSON to this file
+        #[arg(long, value_name = "FILE")]
+        json: Option<PathBuf>,
+    },
+    /// Toss inbound mail as it arrives, until SIGTERM
+    #[cfg(target_os = "linux")]
+    Watch,
+    /// Print the original text of a stored message
+    Raw {
+        /// Echo tag, `netmail` or `.quarantine`
+        area: String,
+        /// Message id
+        id: i64,
+    },
+    /// Netmail tools
+    #[command(subcommand)]
+    Netmail(Netmail),
+    /// Files which failed to toss
+    #[command(subcommand)]
+    Bad(Bad),
+}
+
+#[derive(Subcommand)]
+pub enum Netmail {
+    /// Show the route a netmail took with hop latencies
+    Trace {
+        /// Message id
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum Bad {
+    /// Show bad files with the reasons they failed
+    List,
+    /// Toss bad files again, those which succeed are removed from bad mail
+    Retoss {
+        /// Every bad file
+        #[arg(long, conflicts_with = "file")]
+        all: bool,
+        /// Name of a bad file
+        #[arg(required_unless_present = "all")]
+        file: Option<String>,
+    },
 }
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+/// Message attributes: FTS-0001 bits in the low word, FSC-0053 `^AFLAGS` extensions in the high one.
+/// The tosser acts on three of them when it stores netmail, see `netmail::track`: RRQ of netmail
+/// to us is answered with a return receipt, AuditRequest of transit netmail with an audit trail,
+/// and IsReturnReceipt marks those answers, which are never answered in turn. The other flags
+/// are kept for the tools which read the message base.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash, Debug)]
+pub struct Attributes(u32);
+
+impl Attributes {
+    pub const PRIVATE: Self = Self(1 << 0);
+    pub const CRASH: Self = Self(1 << 1);
+    pub const RECEIVED: Self = Self(1 << 2);
+    pub const SENT: Self = Self(1 << 3);
+    pub const FILE_ATTACHED: Self = Self(1 << 4);
+    pub const IN_TRANSIT: Self = Self(1 << 5);
+    pub const ORPHAN: Self = Self(1 << 6);
+    pub const KILL_SENT: Self = Self(1 << 7);
+    pub const LOCAL: Self = Self(1 << 8);
+    pub const HOLD: Self = Self(1 << 9);
+    pub const FILE_REQUEST: Self = Self(1 << 11);
+    pub const RETURN_RECEIPT_REQUEST: Self = Self(1 << 12);
+    pub const IS_RETURN_RECEIPT: Self = Self(1 << 13);
+    pub const AUDIT_REQUEST: Self = Self(1 << 14);
+    pub const FILE_UPDATE_REQUEST: Self = Self(1 << 15);
+
+    pub const ARCHIVE_SENT: Self = Self(1 << 16);
+    pub const DIRECT: Self = Self(1 << 17);
+    pub const ZONE_GATE: Self = Self(1 << 18);
+    pub const HUB_ROUTE: Self = Self(1 << 19);
+    pub const IMMEDIATE: Self = Self(1 << 20);
+    pub const XMAIL: Self = Self(1 << 21);
+    pub const KILL_FILE_SENT: Self = Self(1 << 22);
+    pub const TRUNCATE_FILE_SENT: Self = Self(1 << 23);
+    pub const LOCK: Self = Self(1 << 24);
+    pub const CONFIRM_RECEIPT_REQUEST: Self = Self(1 << 25);
+    pub const HI_RES_FAX: Self = Self(1 << 26);
+    pub const COVER_LETTER: Self = Self(1 << 27);
+    pub const SIGNATURE: Self = Self(1 << 28);
+    pub const LETTERHEAD: Self = Self(1 << 29);
+    pub const FAX: Self = Self(1 << 30);
+    pub const FORCE_PICKUP: Self = Self(1 << 31);
+
+    /// Names of the bits: FSC-0053 `^AFLAGS` tokens, FTS-0001 names for the bits which have no token
+    pub const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::PRIVATE, "PVT"),
+        (Self::CRASH, "CRA"),
+        (Self::RECEIVED, "RCV"),
+        (Self::SENT, "SNT"),
+        (Self::FILE_ATTACHED, "FIL"),
+        (Self::IN_TRANSIT, "InTransit"),
+        (Self::ORPHAN, "Orphan"),
+        (Self::KILL_SENT, "K/S"),
+        (Self::LOCAL, "Local"),
+        (Self::HOLD, "HLD"),
+        (Self::FILE_REQUEST, "FRQ"),
+        (Self::RETURN_RECEIPT_REQUEST, "RRQ"),
+        (Self::IS_RETURN_RECEIPT, "IsReturnReceipt"),
+        (Self::AUDIT_REQUEST, "AuditRequest"),
+        (Self::FILE_UPDATE_REQUEST, "FileUpdateReq"),
+        (Self::ARCHIVE_SENT, "A/S"),
+        (Self::DIRECT, "DIR"),
+        (Self::ZONE_GATE, "ZON"),
+        (Self::HUB_ROUTE, "HUB"),
+        (Self::IMMEDIATE, "IMM"),
+        (Self::XMAIL, "XMA"),
+        (Self::KILL_FILE_SENT, "KFS"),
+        (Self::TRUNCATE_FILE_SENT, "TFS"),
+        (Self::LOCK, "LOK"),
+        (Self::CONFIRM_RECEIPT_REQUEST, "CFM"),
+        (Self::HI_RES_FAX, "HIR"),
+        (Self::COVER_LETTER, "COV"),
+        (Self::SIGNATURE, "SIG"),
+        (Self::LETTERHEAD, "LET"),
+        (Self::FAX, "FAX"),
+        (Self::FORCE_PICKUP, "FPU"),
+    ];
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    /// Merge flags of a `^AFLAGS` kludge (FSC-0053), unknown ones are ignored
+    pub fn add_flags_kludge(&mut self, s: &str) {
+        for w in s.split_whitespace() {
+            if let Some((a, _)) = Self::NAMES
+                .iter()
+                .filter(|(_, name)| is_token(name))
+                .find(|(_, name)| name.eq_ignore_ascii_case(w))
+            {
+                self.insert(*a);
+            }
+        }
+    }
+}
+
+/// Tokens of `^AFLAGS` defined by FSC-0053
+const FLAGS_TOKENS: &[&str] = &[
+    "PVT", "HLD", "CRA", "K/S", "SNT", "RCV", "A/S", "DIR", "ZON", "HUB", "FIL", "FRQ", "IMM", "XMA", "KFS", "TFS",
+    "LOK", "RRQ", "CFM", "HIR", "COV", "SIG", "LET", "FAX", "FPU",
+];
+
+fn is_token(name: &str) -> bool {
+    FLAGS_TOKENS.contains(&name)
+}
+
+impl From<u16> for Attributes {
+    fn from(flags: u16) -> Self {
+        Self(flags.into())
+    }
+}
+
+impl BitOr for Attributes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Attributes {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl fmt::Display for Attributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut i = Self::NAMES.iter().filter(|(a, _)| self.contains(*a)).peekable();
+
+        while let Some((_, name)) = i.next() {
+            f.write_str(name)?;
+
+            if i.peek().is_some() {
+                f.write_str(" ")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Attributes, FLAGS_TOKENS};
+
+    #[test]
+    fn attributes_from_header() {
+        let a = Attributes::from(0x1181);
+
+        assert!(a.contains(Attributes::PRIVATE));
+        assert!(a.contains(Attributes::KILL_SENT));
+        assert!(a.contains(Attributes::LOCAL));
+        assert!(a.contains(Attributes::RETURN_RECEIPT_REQUEST));
+        assert!(!a.contains(Attributes::CRASH));
+        assert_eq!(a.to_string(), "PVT K/S Local RRQ");
+    }
+
+    #[test]
+    fn attributes_from_flags_kludge() {
+        let mut a = Attributes::from(0x0001);
+        a.add_flags_kludge("DIR imm KFS XYZ A/S LOC local");
+
+        assert_eq!(
+            a,
+            Attributes::PRIVATE
+                | Attributes::DIRECT
+                | Attributes::IMMEDIATE
+                | Attributes::KILL_FILE_SENT
+                | Attributes::ARCHIVE_SENT
+        );
+        assert_eq!(a.to_string(), "PVT A/S DIR IMM KFS");
+
+        // every token has a bit, FTS-0001 names aren't tokens
+        for t in FLAGS_TOKENS {
+            assert!(Attributes::NAMES.iter().any(|(_, name)| name == t), "{t}");
+        }
+
+        let mut a = Attributes::default();
+        a.add_flags_kludge("InTransit AuditRequest ARQ");
+        assert_eq!(a, Attributes::default());
+    }
+}
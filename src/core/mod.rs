@@ -1,11 +1,27 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{Duration, FixedOffset, NaiveDate, NaiveDateTime};
 use encoding::{all::IBM866, DecoderTrap, Encoding};
+use log::warn;
 use std::error::Error;
 use std::num::{IntErrorKind, ParseIntError};
 use std::str::FromStr;
 
-/// Fidonet address according to FRL-1002
-#[derive(Eq, PartialEq, Debug)]
+mod attributes;
+
+pub use attributes::Attributes;
+
+/// Fidonet address according to FRL-1002, domains are compared case-insensitively
+///
+/// ```
+/// use corona::core::Address;
+///
+/// let a: Address = "2:5020/100.0@FidoNet".parse()?;
+///
+/// assert_eq!(a, Address::new_5d(2, 5020, 100, 0, "fidonet"));
+/// assert!(a.same_3d(&Address::new_3d(2, 5020, 100)));
+/// assert_eq!(a.to_string(), "2:5020/100@fidonet");
+/// # Ok::<(), corona::core::ParseAddressError>(())
+/// ```
+#[derive(Clone, Debug)]
 pub struct Address {
     pub zone: u16,
     pub net: u16,
@@ -19,19 +35,19 @@ impl Address {
         Self::full(0, 0, 0, 0, None)
     }
 
-    /*pub fn new_3d(zone: u16, net: u16, node: u16) -> Self {
+    pub fn new_3d(zone: u16, net: u16, node: u16) -> Self {
         Self::full(zone, net, node, 0, None)
-    }*/
+    }
 
     pub fn new_4d(zone: u16, net: u16, node: u16, point: u16) -> Self {
         Self::full(zone, net, node, point, None)
     }
 
-    /*pub fn new_5d(zone: u16, net: u16, node: u16, point: u16, domain: String) -> Self {
-        Self::full(zone, net, node, point, Some(domain))
-    }*/
+    pub fn new_5d(zone: u16, net: u16, node: u16, point: u16, domain: &str) -> Self {
+        Self::full(zone, net, node, point, Some(domain.to_string()))
+    }
 
-    fn full(zone: u16, net: u16, node: u16, point: u16, domain: Option<String>) -> Self {
+    pub fn full(zone: u16, net: u16, node: u16, point: u16, domain: Option<String>) -> Self {
         Self {
             zone,
             net,
@@ -40,11 +56,87 @@ impl Address {
             domain,
         }
     }
+
+    /// Same zone, net and node. Domains matter only if both are known.
+    pub fn same_3d(&self, other: &Address) -> bool {
+        self.zone == other.zone
+            && self.net == other.net
+            && self.node == other.node
+            && match (&self.domain, &other.domain) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                _ => true,
+            }
+    }
+
+    /// Same as `same_3d` and the same point
+    pub fn same_4d(&self, other: &Address) -> bool {
+        self.same_3d(other) && self.point == other.point
+    }
+
+    fn domain_bytes(&self) -> Option<impl Iterator<Item = u8> + '_> {
+        self.domain.as_ref().map(|d| d.bytes().map(|b| b.to_ascii_lowercase()))
+    }
+}
+
+impl PartialEq for Address {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Address {}
+
+impl PartialOrd for Address {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Zone, net, node, point, then domain. Addresses without a domain go first.
+impl Ord for Address {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.zone, self.net, self.node, self.point)
+            .cmp(&(other.zone, other.net, other.node, other.point))
+            .then_with(|| match (self.domain_bytes(), other.domain_bytes()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            })
+    }
+}
+
+impl std::hash::Hash for Address {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.zone, self.net, self.node, self.point).hash(state);
+
+        if let Some(domain) = self.domain_bytes() {
+            domain.for_each(|b| b.hash(state));
+        }
+    }
+}
+
+/// Canonical form `2:5020/100.1@fidonet`: no `.0` point, lowercase domain
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}/{}", self.zone, self.net, self.node)?;
+
+        if self.point != 0 {
+            write!(f, ".{}", self.point)?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "@{}", domain.to_ascii_lowercase())?;
+        }
+
+        Ok(())
+    }
 }
 
 impl From<crate::ftn::Address> for Address {
     fn from(a: crate::ftn::Address) -> Self {
-        Self::new_4d(a.zone, a.net, a.node, a.point)
+        Self {
+            domain: a.domain,
+            ..Self::new_4d(a.zone, a.net, a.node, a.point)
+        }
     }
 }
 
@@ -64,6 +156,8 @@ impl std::fmt::Display for ParseAddressError {
     }
 }
 
+impl Error for ParseAddressError {}
+
 impl FromStr for Address {
     type Err = ParseAddressError;
 
@@ -136,6 +230,100 @@ impl FromStr for Address {
     }
 }
 
+/// Address with `*` for any zone, net, node or point, e.g. `2:5020/*`, `2:5020/100.*` or `*@fidonet`.
+/// A missing point means the node itself. A domain is checked only if the address has one.
+///
+/// ```
+/// use corona::core::AddressPattern;
+///
+/// let p: AddressPattern = "2:5020/*".parse()?;
+///
+/// assert!(p.matches(&"2:5020/100.1".parse()?));
+/// assert!(!p.matches(&"2:5030/100".parse()?));
+/// # Ok::<(), corona::core::ParseAddressError>(())
+/// ```
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct AddressPattern {
+    zone: Option<u16>,
+    net: Option<u16>,
+    node: Option<u16>,
+    point: Option<u16>,
+    domain: Option<String>,
+}
+
+impl AddressPattern {
+    pub fn matches(&self, a: &Address) -> bool {
+        let part = |p: Option<u16>, v: u16| p.is_none_or(|p| p == v);
+
+        part(self.zone, a.zone)
+            && part(self.net, a.net)
+            && part(self.node, a.node)
+            && part(self.point, a.point)
+            && match (&self.domain, &a.domain) {
+                (Some(p), Some(d)) => p.eq_ignore_ascii_case(d),
+                _ => true,
+            }
+    }
+
+    /// Whether some addresses of `zone` match
+    pub fn in_zone(&self, zone: u16) -> bool {
+        self.zone.is_none_or(|z| z == zone)
+    }
+}
+
+impl FromStr for AddressPattern {
+    type Err = ParseAddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let part = |s: &str| match s {
+            "*" => Ok(None),
+            _ => s.parse().map(Some).map_err(|e: ParseIntError| match e.kind() {
+                IntErrorKind::PosOverflow => Self::Err::Overflow,
+                _ => Self::Err::InvalidFormat,
+            }),
+        };
+
+        let (addr, domain) = match s.split_once('@') {
+            Some((addr, "*")) => (addr, None),
+            Some((addr, domain)) => (addr, Some(domain.to_string())),
+            None => (s, None),
+        };
+
+        let mut p = Self {
+            zone: None,
+            net: None,
+            node: None,
+            point: None,
+            domain,
+        };
+
+        if addr == "*" {
+            return Ok(p);
+        }
+
+        let (zone, rest) = addr.split_once(':').ok_or(Self::Err::InvalidFormat)?;
+        p.zone = part(zone)?;
+
+        if rest == "*" {
+            return Ok(p);
+        }
+
+        let (net, rest) = rest.split_once('/').ok_or(Self::Err::InvalidFormat)?;
+        p.net = part(net)?;
+
+        let (node, point) = match rest.split_once('.') {
+            Some((node, point)) => (node, point),
+            None if rest == "*" => (rest, "*"),
+            None => (rest, "0"),
+        };
+
+        p.node = part(node)?;
+        p.point = part(point)?;
+
+        Ok(p)
+    }
+}
+
 #[derive(Debug)]
 pub struct User {
     pub addr: Address,
@@ -149,6 +337,154 @@ pub enum Area {
     Echomail(String),
 }
 
+impl Area {
+    /// File name of the message base, `None` for an echo tag which would name a file
+    /// outside of the area bases
+    pub fn base_name(&self) -> Option<String> {
+        match self {
+            Area::Netmail => Some("netmail".to_string()),
+            Area::Echomail(name) if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) => None,
+            Area::Echomail(name) => Some(name.to_ascii_lowercase()),
+        }
+    }
+}
+
+/// Control line (`^A` kludge) split into a name and a value
+#[derive(Eq, PartialEq, Debug)]
+pub struct Kludge {
+    pub name: String,
+    pub value: String,
+}
+
+impl Kludge {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn from_token(t: &Token, s: &str) -> Option<Self> {
+        let name = match t {
+            Token::MsgId => MSGID,
+            Token::Reply => REPLY,
+            Token::Pid => PID,
+            Token::Tid => TID,
+            Token::TzUtc => TZUTC,
+            Token::Path => PATH,
+            Token::Kludge => return Some(Self::from(s)),
+            _ => return None,
+        };
+
+        Some(Self::new(name.trim_end_matches([':', ' ']), s))
+    }
+}
+
+impl From<&str> for Kludge {
+    /// Name ends either with a colon (`CHRS: CP866 2`) or with a space (`INTL 2:5020/1 2:5020/100`)
+    fn from(s: &str) -> Self {
+        match s.find([':', ' ']) {
+            Some(i) => Self::new(&s[..i], s[i + 1..].trim_start()),
+            None => Self::new(s, ""),
+        }
+    }
+}
+
+/// Netmail hop according to FTS-4009: `Via <address> @YYYYMMDD.HHMMSS[.UTC] <program> <version>`
+#[derive(Eq, PartialEq, Debug)]
+pub struct Via {
+    pub addr: Address,
+    pub at: Option<NaiveDateTime>,
+    pub utc: bool,
+    pub software: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseViaError {
+    InvalidFormat,
+}
+
+impl std::fmt::Display for ParseViaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidFormat => "Format is not valid. It should be `address @YYYYMMDD.HHMMSS program version`.",
+        }
+        .fmt(f)
+    }
+}
+
+impl Error for ParseViaError {}
+
+impl FromStr for Via {
+    type Err = ParseViaError;
+
+    /// Besides FTS-4009 lines, older ones like `FidoGate 2:5020/1, Fri Feb 28 2020 at 14:00` are accepted:
+    /// the first word which looks like an address is taken, the rest is treated as a software name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut addr = None;
+        let mut at = None;
+        let mut utc = false;
+        let mut software = Vec::new();
+        let mut after_addr = false;
+
+        for w in s.split_whitespace() {
+            if addr.is_none() {
+                if let Ok(a) = Address::from_str(w.trim_end_matches(',')) {
+                    addr = Some(a);
+                    after_addr = true;
+                    continue;
+                }
+            } else if std::mem::take(&mut after_addr) {
+                if let Some(ts) = w.strip_prefix('@') {
+                    let mut i = ts.split('.');
+
+                    let dt = match (i.next(), i.next()) {
+                        (Some(date), Some(time)) => {
+                            NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y%m%d %H%M%S").ok()
+                        }
+                        _ => None,
+                    };
+
+                    if dt.is_some() {
+                        at = dt;
+                        utc = i.any(|x| x.eq_ignore_ascii_case("UTC"));
+                        continue;
+                    }
+                }
+            }
+
+            software.push(w);
+        }
+
+        Ok(Self {
+            addr: addr.ok_or(Self::Err::InvalidFormat)?,
+            at,
+            utc,
+            software: software.join(" "),
+        })
+    }
+}
+
+impl std::fmt::Display for Via {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.addr)?;
+
+        if let Some(at) = self.at {
+            write!(f, " @{}", at.format("%Y%m%d.%H%M%S"))?;
+
+            if self.utc {
+                write!(f, ".UTC")?;
+            }
+        }
+
+        if !self.software.is_empty() {
+            write!(f, " {}", self.software)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ControlLines {
     pub pid: Option<String>,
@@ -156,18 +492,21 @@ pub struct ControlLines {
     pub tzutc: Option<String>,
     pub seen_by: Option<Vec<NetNodePair>>,
     pub path: Option<Vec<NetNodePair>>,
-    pub custom: Option<Vec<String>>,
+    /// All `^A` kludges in the order of appearance, including the parsed ones
+    pub lines: Option<Vec<Kludge>>,
+    pub via: Option<Vec<Via>>,
 }
 
 impl ControlLines {
-    fn empty() -> Self {
+    pub fn empty() -> Self {
         Self {
             pid: None,
             tid: None,
             tzutc: None,
             seen_by: None,
             path: None,
-            custom: None,
+            lines: None,
+            via: None,
         }
     }
 }
@@ -176,9 +515,11 @@ impl ControlLines {
 pub struct Message {
     pub area: Area,
     pub posted: NaiveDateTime,
+    /// How `posted` was written, `None` if it was taken from the packet header
+    pub posted_format: Option<DateFormat>,
     pub from: User,
     pub to: User,
-    pub flags: u16,
+    pub flags: Attributes,
     pub msgid_serial: u32,
     pub reply_serial: Option<u32>,
     pub msgid_addr: Option<String>,
@@ -188,59 +529,112 @@ pub struct Message {
     pub tear_line: String,
     pub origin: String,
     pub kludges: ControlLines,
+    /// Untouched message text as it came in the package
+    pub raw: Vec<u8>,
+}
+
+impl Message {
+    /// Offset of `posted` from UTC according to TZUTC, `None` if the zone is unknown
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        self.kludges.tzutc.as_deref().and_then(parse_tzutc)
+    }
+
+    /// `posted` in UTC, `None` if the zone is unknown
+    pub fn posted_utc(&self) -> Option<NaiveDateTime> {
+        self.utc_offset()
+            .map(|o| self.posted - Duration::seconds(o.local_minus_utc().into()))
+    }
 }
 
 type TokenPair<'a> = (Token, &'a str);
 
-pub fn messages_from(pkg: crate::ftn::Package) -> Result<Vec<Message>, Box<dyn Error>> {
-    let mut ret = Vec::new();
+/// `field` of a message is not valid CP866
+#[derive(Debug)]
+pub struct DecodeError {
+    pub field: &'static str,
+    pub reason: std::borrow::Cow<'static, str>,
+}
 
-    for m in pkg.messages {
-        let posted = String::from_utf8(m.posted)?;
-        let posted = match parse_ftn_datetime(&posted) {
-            Ok(dt) => dt,
-            Err(e) => {
-                eprintln!(
-                    "Warning: failed to parse posted date \"{}\", reason: \"{:?}\". Falling back to pkg create date.",
-                    &posted, e
-                );
-                pkg.created
-            }
-        };
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unable to decode {}: {}", self.field, self.reason)
+    }
+}
 
-        let mut msg = Message {
-            area: Area::Netmail,
-            posted,
-            from: User {
-                addr: m.from.address.into(),
-                name: IBM866.decode(&m.from.name, DecoderTrap::Strict)?,
-                ext_addr: None,
-            },
-            to: User {
-                addr: m.to.address.into(),
-                name: IBM866.decode(&m.to.name, DecoderTrap::Strict)?,
-                ext_addr: None,
-            },
-            flags: m.flags,
-            msgid_serial: 0,
-            reply_serial: None,
-            msgid_addr: None,
-            reply_addr: None,
-            subj: IBM866.decode(&m.subj, DecoderTrap::Strict)?, // DecoderTrap::Ignore
-            body: String::with_capacity(m.text.len()),
-            tear_line: String::new(),
-            origin: String::new(),
-            kludges: ControlLines::empty(),
-        };
+impl Error for DecodeError {}
 
-        let text = IBM866.decode(&m.text, DecoderTrap::Strict)?.replace('\r', "\n");
+fn decode(field: &'static str, b: &[u8]) -> Result<String, DecodeError> {
+    IBM866
+        .decode(b, DecoderTrap::Strict)
+        .map_err(|reason| DecodeError { field, reason })
+}
 
-        parse_tokens(&tokenize_msg_body(&text)?, &mut msg)?;
+/// Decodes and parses a packed message, `header` is the one of the packet it came in.
+/// The original text is kept in `raw` only if `keep_raw` is true for the area of the message.
+pub fn message_from(
+    header: &crate::ftn::Header,
+    m: crate::ftn::Message,
+    keep_raw: impl Fn(&Area) -> bool,
+) -> Result<Message, DecodeError> {
+    let posted = String::from_utf8_lossy(&m.posted);
+    let (posted, posted_format) = match parse_ftn_datetime(&posted) {
+        Ok((dt, format)) => (dt, Some(format)),
+        Err(e) => {
+            warn!(
+                "failed to parse posted date \"{}\", reason: \"{:?}\". Falling back to pkg create date.",
+                &posted, e
+            );
+            (
+                header.created.unwrap_or_else(|| chrono::Local::now().naive_local()),
+                None,
+            )
+        }
+    };
 
-        ret.push(msg);
+    let mut hints = AddressHints::new(
+        header.orig.clone().into(),
+        header.dest.clone().into(),
+        (m.from.net, m.from.node),
+        (m.to.net, m.to.node),
+    );
+
+    let mut msg = Message {
+        area: Area::Netmail,
+        posted,
+        posted_format,
+        from: User {
+            addr: Address::empty(),
+            name: decode("from", &m.from.name)?,
+            ext_addr: None,
+        },
+        to: User {
+            addr: Address::empty(),
+            name: decode("to", &m.to.name)?,
+            ext_addr: None,
+        },
+        flags: m.flags.into(),
+        msgid_serial: 0,
+        reply_serial: None,
+        msgid_addr: None,
+        reply_addr: None,
+        subj: decode("subject", &m.subj)?, // DecoderTrap::Ignore
+        body: String::with_capacity(m.text.len()),
+        tear_line: String::new(),
+        origin: String::new(),
+        kludges: ControlLines::empty(),
+        raw: Vec::new(),
+    };
+
+    let text = decode("text", &m.text)?.replace('\r', "\n");
+
+    parse_tokens(&tokenize_msg_body(&text), &mut msg, &mut hints);
+    resolve_addresses(&mut msg, hints);
+
+    if keep_raw(&msg.area) {
+        msg.raw = m.text;
     }
 
-    Ok(ret)
+    Ok(msg)
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -270,14 +664,14 @@ const TID: &str = "TID: ";
 const TZUTC: &str = "TZUTC: ";
 const PATH: &str = "PATH: ";
 
-const REPLYADDR: &str = "REPLYADDR: ";
-const REPLYADDR_V2: &str = "REPLYADDR ";
-const REPLYTO: &str = "REPLYTO: ";
-const REPLYTO_V2: &str = "REPLYTO ";
+const REPLYADDR: &str = "REPLYADDR";
+const REPLYTO: &str = "REPLYTO";
 
-const INTL: &str = "INTL ";
-const FMPT: &str = "FMPT ";
-const TOPT: &str = "TOPT ";
+const INTL: &str = "INTL";
+const FMPT: &str = "FMPT";
+const TOPT: &str = "TOPT";
+const VIA: &str = "Via";
+const FLAGS: &str = "FLAGS";
 
 const AREA: &str = "AREA:";
 const TEAR_LINE: &str = "--- ";
@@ -286,7 +680,7 @@ const ORIGIN: &str = " * Origin: ";
 const SEEN_BY: &str = "SEEN-BY: ";
 
 // &nbsp; is treated as \u{a0}
-fn tokenize_msg_body(text: &str) -> Result<Vec<TokenPair<'_>>, Box<dyn Error>> {
+fn tokenize_msg_body(text: &str) -> Vec<TokenPair<'_>> {
     let mut tokens = Vec::new();
 
     for par in text.split(NEWLINE) {
@@ -375,14 +769,50 @@ fn tokenize_msg_body(text: &str) -> Result<Vec<TokenPair<'_>>, Box<dyn Error>> {
         }
     }
 
-    Ok(tokens)
+    tokens
 }
 
-fn parse_tokens(tokens: &[TokenPair], msg: &mut Message) -> Result<(), Box<dyn Error>> {
-    let mut native_from = None;
-    let mut native_to = None;
+/// Addresses a message can be routed by, collected from the packet and the message itself
+#[derive(Debug)]
+struct AddressHints {
+    /// Packet header addresses
+    pkt_orig: Address,
+    pkt_dest: Address,
+    /// Packed message header knows only net/node
+    from: NetNodePair,
+    to: NetNodePair,
+    msgid: Option<Address>,
+    reply: Option<Address>,
+    replyto: Option<Address>,
+    /// `INTL <dest> <orig>`
+    intl: Option<(Address, Address)>,
+    fmpt: Option<u16>,
+    topt: Option<u16>,
+}
+
+impl AddressHints {
+    fn new(pkt_orig: Address, pkt_dest: Address, from: NetNodePair, to: NetNodePair) -> Self {
+        Self {
+            pkt_orig,
+            pkt_dest,
+            from,
+            to,
+            msgid: None,
+            reply: None,
+            replyto: None,
+            intl: None,
+            fmpt: None,
+            topt: None,
+        }
+    }
+}
 
+fn parse_tokens(tokens: &[TokenPair], msg: &mut Message, hints: &mut AddressHints) {
     for (t, s) in tokens {
+        if let Some(kl) = Kludge::from_token(t, s) {
+            msg.kludges.lines.get_or_insert(Vec::new()).push(kl);
+        }
+
         match t {
             Token::Area => {
                 msg.area = Area::Echomail(s.trim().to_string());
@@ -392,11 +822,11 @@ fn parse_tokens(tokens: &[TokenPair], msg: &mut Message) -> Result<(), Box<dyn E
                     msg.msgid_serial = id.serial;
 
                     match id.addr {
-                        AddressKind::Native(a) => native_from = Some(a),
+                        AddressKind::Native(a) => hints.msgid = Some(a),
                         AddressKind::External(a) => msg.msgid_addr = Some(a),
                     }
                 } else {
-                    eprintln!("MSGID parse fail: {}", s);
+                    warn!("MSGID parse fail: {}", s);
                 }
             }
             Token::Reply => {
@@ -404,11 +834,11 @@ fn parse_tokens(tokens: &[TokenPair], msg: &mut Message) -> Result<(), Box<dyn E
                     msg.reply_serial = Some(id.serial);
 
                     match id.addr {
-                        AddressKind::Native(a) => native_to = Some(a),
+                        AddressKind::Native(a) => hints.reply = Some(a),
                         AddressKind::External(a) => msg.reply_addr = Some(a),
                     }
                 } else {
-                    eprintln!("REPLY parse fail: {}", s);
+                    warn!("REPLY parse fail: {}", s);
                 }
             }
             Token::Pid => {
@@ -428,29 +858,51 @@ fn parse_tokens(tokens: &[TokenPair], msg: &mut Message) -> Result<(), Box<dyn E
             }
             Token::SeenBy(skip) => match &mut parse_net_node_pairs(s[*skip..].trim()) {
                 Ok(v) => msg.kludges.seen_by.get_or_insert(Vec::new()).append(v),
-                Err(_) => eprintln!("SEEN-BY parse fail: {}", s),
+                Err(_) => warn!("SEEN-BY parse fail: {}", s),
             },
             Token::Path => match &mut parse_net_node_pairs(s.trim()) {
                 Ok(v) => msg.kludges.path.get_or_insert(Vec::new()).append(v),
-                Err(_) => eprintln!("PATH parse fail: {}", s),
+                Err(_) => warn!("PATH parse fail: {}", s),
             },
             Token::Kludge => {
-                if let Some(suffix) = s.strip_prefix(REPLYADDR) {
-                    msg.from.ext_addr = Some(suffix.to_string());
-                } else if let Some(suffix) = s.strip_prefix(REPLYADDR_V2) {
-                    msg.from.ext_addr = Some(suffix.to_string());
-                } else {
-                    if let Some(suffix) = s.strip_prefix(REPLYTO) {
-                        if let Ok((a, _name)) = parse_replyto(suffix.trim()) {
-                            native_from = Some(a);
-                        }
-                    } else if let Some(suffix) = s.strip_prefix(REPLYTO_V2) {
-                        if let Ok((a, _name)) = parse_replyto(suffix.trim()) {
-                            native_from = Some(a);
+                let kl = Kludge::from(*s);
+
+                match kl.name.as_str() {
+                    REPLYADDR => {
+                        msg.from.ext_addr = Some(kl.value);
+                    }
+                    REPLYTO => {
+                        if let Ok((a, _name)) = parse_replyto(kl.value.trim()) {
+                            hints.replyto = Some(a);
                         }
                     }
+                    FLAGS => {
+                        msg.flags.add_flags_kludge(&kl.value);
+                    }
+                    VIA => match Via::from_str(&kl.value) {
+                        Ok(v) => msg.kludges.via.get_or_insert(Vec::new()).push(v),
+                        Err(_) => warn!("Via parse fail: {}", kl.value),
+                    },
+                    INTL => {
+                        let mut i = kl.value.split_whitespace();
+
+                        let dest = i.next().and_then(|x| Address::from_str(x).ok());
+                        let orig = i.next().and_then(|x| Address::from_str(x).ok());
 
-                    msg.kludges.custom.get_or_insert(Vec::new()).push(s.to_string());
+                        match (dest, orig) {
+                            (Some(dest), Some(orig)) => hints.intl = Some((dest, orig)),
+                            _ => warn!("INTL parse fail: {}", kl.value),
+                        }
+                    }
+                    FMPT => match u16::from_str(kl.value.trim()) {
+                        Ok(val) => hints.fmpt = Some(val),
+                        Err(_) => warn!("FMPT parse fail: {}", kl.value),
+                    },
+                    TOPT => match u16::from_str(kl.value.trim()) {
+                        Ok(val) => hints.topt = Some(val),
+                        Err(_) => warn!("TOPT parse fail: {}", kl.value),
+                    },
+                    _ => {}
                 }
             }
             Token::Paragraph => {
@@ -464,82 +916,66 @@ fn parse_tokens(tokens: &[TokenPair], msg: &mut Message) -> Result<(), Box<dyn E
     if msg.body.ends_with(NEWLINE) {
         msg.body.pop();
     }
+}
 
-    // as a last resort - parse address from Origin
-    if native_from.is_none() && !msg.origin.is_empty() {
-        let mut i = msg.origin.char_indices().rev().filter(|(_, c)| c == &'(' || c == &')');
-
-        match (i.next(), i.next()) {
-            (Some((end, ')')), Some((start, '('))) if start + 3 < end => {
-                if let Ok(a) = Address::from_str(&msg.origin[start + 1..end]) {
-                    native_from = Some(a);
-                }
-            }
-            _ => {}
-        }
+/// Sets FROM and TO addresses of a parsed message.
+///
+/// Netmail goes by the packed message header: zone comes from the packet header unless there's INTL,
+/// points come from FMPT and TOPT. If FMPT is missing and the message originates from the packet sender,
+/// the point of the packet header is taken.
+///
+/// Echomail FROM is taken from the first of REPLYTO, MSGID and Origin which has an address and falls back
+/// to the packed message header. TO is known only for replies, from REPLY.
+fn resolve_addresses(msg: &mut Message, hints: AddressHints) {
+    let header_addr = |zone: u16, (net, node): NetNodePair| Address::new_3d(zone, net, node);
+
+    let mut from = header_addr(hints.pkt_orig.zone, hints.from);
+    let mut to = header_addr(hints.pkt_dest.zone, hints.to);
+
+    if let Some((dest, orig)) = &hints.intl {
+        from = header_addr(orig.zone, (orig.net, orig.node));
+        to = header_addr(dest.zone, (dest.net, dest.node));
     }
 
-    // set FROM
-    if let Some(a) = native_from {
-        msg.from.addr = a;
+    if from.same_3d(&hints.pkt_orig) {
+        from.point = hints.pkt_orig.point;
     }
 
-    // set TO
-    msg.to.addr = if let Some(a) = native_to { a } else { Address::empty() };
+    match msg.area {
+        Area::Netmail => {
+            if let Some(point) = hints.fmpt {
+                from.point = point;
+            }
 
-    // why not?
-    if msg.reply_serial.is_none() && msg.to.name.to_ascii_lowercase() == "all" {
-        msg.to.addr = Address::empty();
-        msg.to.ext_addr = None;
-    }
+            to.point = hints.topt.unwrap_or(0);
+        }
+        Area::Echomail(_) => {
+            // as a last resort - parse address from Origin
+            let mut i = msg.origin.char_indices().rev().filter(|(_, c)| c == &'(' || c == &')');
 
-    if msg.area == Area::Netmail {
-        // correct from FROM and TO using INTL, FMPT, TOPT
-        if let Some(kludges) = &msg.kludges.custom {
-            for s in kludges.iter().filter(|s| s.len() > 5) {
-                match &s[..5] {
-                    INTL => {
-                        let mut i = s[INTL.len()..].trim().split(' ');
+            let origin = match (i.next(), i.next()) {
+                (Some((end, ')')), Some((start, '('))) if start + 3 < end => {
+                    Address::from_str(&msg.origin[start + 1..end]).ok()
+                }
+                _ => None,
+            };
 
-                        let dest = i.next().and_then(|x| Address::from_str(x).ok());
-                        let orig = i.next().and_then(|x| Address::from_str(x).ok());
+            if let Some(a) = hints.replyto.or(hints.msgid).or(origin) {
+                from = a;
+            }
 
-                        match (dest, orig) {
-                            (Some(dest), Some(orig)) => {
-                                msg.to.addr.zone = dest.zone;
-                                msg.to.addr.net = dest.net;
-                                msg.to.addr.node = dest.node;
-
-                                msg.from.addr.zone = orig.zone;
-                                msg.from.addr.net = orig.net;
-                                msg.from.addr.node = orig.node;
-                            }
-                            _ => {
-                                eprintln!("INTL parse fail: {}", s);
-                            }
-                        }
-                    }
-                    FMPT => {
-                        if let Ok(val) = u16::from_str(s[FMPT.len()..].trim()) {
-                            msg.from.addr.point = val;
-                        } else {
-                            eprintln!("FMPT parse fail: {}", s);
-                        }
-                    }
-                    TOPT => {
-                        if let Ok(val) = u16::from_str(s[TOPT.len()..].trim()) {
-                            msg.to.addr.point = val;
-                        } else {
-                            eprintln!("TOPT parse fail: {}", s);
-                        }
-                    }
-                    _ => {}
-                }
+            to = hints.reply.unwrap_or_else(Address::empty);
+
+            // why not?
+            if msg.reply_serial.is_none() && msg.to.name.eq_ignore_ascii_case("all") {
+                to = Address::empty();
+                msg.to.ext_addr = None;
             }
         }
     }
 
-    Ok(())
+    msg.from.addr = from;
+    msg.to.addr = to;
 }
 
 #[derive(Eq, PartialEq, Debug)]
@@ -549,7 +985,7 @@ enum AddressKind {
 }
 
 #[derive(Eq, PartialEq, Debug)]
-struct MessageId {
+pub struct MessageId {
     addr: AddressKind,
     serial: u32,
 }
@@ -584,6 +1020,8 @@ impl std::fmt::Display for ParseMessageIdError {
     }
 }
 
+impl Error for ParseMessageIdError {}
+
 impl std::str::FromStr for MessageId {
     type Err = ParseMessageIdError;
 
@@ -629,6 +1067,8 @@ impl std::fmt::Display for NetNodePairError {
     }
 }
 
+impl Error for NetNodePairError {}
+
 fn parse_net_node_pairs(s: &str) -> Result<Vec<NetNodePair>, NetNodePairError> {
     let map_parse_int_err = |err: ParseIntError| match err.kind() {
         IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => NetNodePairError::Overflow,
@@ -692,62 +1132,191 @@ const FTN_MONTHS: &[&str] = &[
     "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
 ];
 
-fn parse_ftn_datetime(s: &str) -> Result<NaiveDateTime, DateTimeError> {
-    if s.len() != 19 {
-        return Err(DateTimeError::Format);
+const FTN_WEEKDAYS: &[&str] = &["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+const ISO_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Layout of a posted date
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DateFormat {
+    /// `DD Mon YY  HH:MM:SS`, the only one FTS-0001 allows
+    Fts0001,
+    /// `Www DD Mon YY HH:MM`
+    SeaDog,
+    /// FTS-0001 fields with other spacing, without seconds or with a four digit year
+    Variant,
+    /// `YYYY-MM-DD HH:MM:SS`, `T` instead of the space and no seconds are fine too
+    Iso8601,
+}
+
+impl DateFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fts0001 => "fts-0001",
+            Self::SeaDog => "seadog",
+            Self::Variant => "variant",
+            Self::Iso8601 => "iso-8601",
+        }
     }
+}
 
-    let map_parse_int_err = |_| DateTimeError::Format;
-
-    match (
-        &s[..2],
-        &s[2..3],
-        &s[3..6],
-        &s[6..7],
-        &s[7..9],
-        &s[9..11],
-        &s[11..13],
-        &s[13..14],
-        &s[14..16],
-        &s[16..17],
-        &s[17..],
-    ) {
-        (day, " ", month, " ", year, "  ", hh, ":", mm, ":", ss) => {
-            let (day, month, mut year) = (
-                day.trim().parse().map_err(map_parse_int_err)?,
-                FTN_MONTHS
-                    .iter()
-                    .position(|&x| x == month)
-                    .ok_or(DateTimeError::Format)? as u32
-                    + 1,
-                year.parse::<u32>().map_err(map_parse_int_err)?,
-            );
+/// FTS-4008 TZUTC value `[-]hhmm`, a leading `+` is tolerated
+pub fn parse_tzutc(s: &str) -> Option<FixedOffset> {
+    let s = s.trim();
 
-            year += if year >= 90 { 1900 } else { 2000 };
+    let (sign, hhmm) = match s.as_bytes().first()? {
+        b'-' => (-1, &s[1..]),
+        b'+' => (1, &s[1..]),
+        _ => (1, s),
+    };
 
-            let (hh, mm, ss) = (
-                hh.parse().map_err(map_parse_int_err)?,
-                mm.parse().map_err(map_parse_int_err)?,
-                ss.parse().map_err(map_parse_int_err)?,
-            );
+    if !(3..=4).contains(&hhmm.len()) || !hhmm.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
 
-            Ok(NaiveDate::from_ymd_opt(year as i32, month, day)
-                .ok_or_else(|| DateTimeError::date(year, month, day))?
-                .and_hms_opt(hh, mm, ss)
-                .ok_or_else(|| DateTimeError::time(hh, mm, ss))?)
-        }
-        _ => Err(DateTimeError::Format),
+    let (hh, mm) = hhmm.split_at(hhmm.len() - 2);
+    let (hh, mm) = (hh.parse::<i32>().ok()?, mm.parse::<i32>().ok()?);
+
+    if hh > 14 || mm > 59 {
+        return None;
     }
+
+    FixedOffset::east_opt(sign * (hh * 3600 + mm * 60))
+}
+
+fn parse_ftn_datetime(s: &str) -> Result<(NaiveDateTime, DateFormat), DateTimeError> {
+    let s = s.trim_end();
+
+    if let Some(dt) = ISO_FORMATS
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+    {
+        return Ok((dt, DateFormat::Iso8601));
+    }
+
+    let mut words: Vec<_> = s.split_whitespace().collect();
+
+    let seadog = words.first().is_some_and(|w| FTN_WEEKDAYS.contains(w));
+
+    if seadog {
+        words.remove(0);
+    }
+
+    let [day, month, year, time] = words[..] else {
+        return Err(DateTimeError::Format);
+    };
+
+    let number = |s: &str, max_len| {
+        if s.is_empty() || s.len() > max_len || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(DateTimeError::Format);
+        }
+
+        s.parse::<u32>().map_err(|_| DateTimeError::Format)
+    };
+
+    let day = number(day, 2)?;
+    let month = FTN_MONTHS
+        .iter()
+        .position(|x| x.eq_ignore_ascii_case(month))
+        .ok_or(DateTimeError::Format)? as u32
+        + 1;
+
+    let year = match year.len() {
+        2 => {
+            let year = number(year, 2)?;
+            year + if year >= 90 { 1900 } else { 2000 }
+        }
+        4 => number(year, 4)?,
+        _ => return Err(DateTimeError::Format),
+    };
+
+    let time: Vec<_> = time.split(':').collect();
+
+    let (hh, mm, ss) = match time[..] {
+        [hh, mm] => (number(hh, 2)?, number(mm, 2)?, 0),
+        [hh, mm, ss] => (number(hh, 2)?, number(mm, 2)?, number(ss, 2)?),
+        _ => return Err(DateTimeError::Format),
+    };
+
+    let dt = NaiveDate::from_ymd_opt(year as i32, month, day)
+        .ok_or_else(|| DateTimeError::date(year, month, day))?
+        .and_hms_opt(hh, mm, ss)
+        .ok_or_else(|| DateTimeError::time(hh, mm, ss))?;
+
+    // day may be either " 3" or "03"
+    let fts0001 = s.len() == 19 && s.is_ascii() && &s[2..3] == " " && &s[9..11] == "  " && time.len() == 3;
+
+    let format = if seadog {
+        DateFormat::SeaDog
+    } else if fts0001 {
+        DateFormat::Fts0001
+    } else {
+        DateFormat::Variant
+    };
+
+    Ok((dt, format))
 }
 
 #[cfg(test)]
 mod test {
     use super::{
-        parse_ftn_datetime, parse_net_node_pairs, parse_replyto, Address, DateTimeError, MessageId, NetNodePairError,
-        ParseAddressError, ParseMessageIdError,
+        parse_ftn_datetime, parse_net_node_pairs, parse_replyto, parse_tokens, parse_tzutc, resolve_addresses,
+        tokenize_msg_body, Address, AddressHints, AddressPattern, Area, Attributes, ControlLines, DateFormat,
+        DateTimeError, Kludge, Message, MessageId, NetNodePairError, ParseAddressError, ParseMessageIdError,
+        ParseViaError, User, Via,
     };
     use std::str::FromStr;
 
+    fn parse_with_hints(text: &str, to: &str, mut hints: AddressHints) -> Message {
+        let user = |name: &str| User {
+            addr: Address::empty(),
+            name: name.to_string(),
+            ext_addr: None,
+        };
+
+        let mut msg = Message {
+            area: Area::Netmail,
+            posted: chrono::NaiveDate::from_ymd(2020, 2, 28).and_hms(14, 0, 18),
+            posted_format: Some(DateFormat::Fts0001),
+            from: user("John Doe"),
+            to: user(to),
+            flags: Attributes::default(),
+            msgid_serial: 0,
+            reply_serial: None,
+            msgid_addr: None,
+            reply_addr: None,
+            subj: String::new(),
+            body: String::new(),
+            tear_line: String::new(),
+            origin: String::new(),
+            kludges: ControlLines::empty(),
+            raw: Vec::new(),
+        };
+
+        parse_tokens(&tokenize_msg_body(text), &mut msg, &mut hints);
+        resolve_addresses(&mut msg, hints);
+
+        msg
+    }
+
+    fn parse_body(text: &str) -> Message {
+        parse_with_hints(
+            text,
+            "All",
+            AddressHints::new(
+                Address::new_4d(2, 5020, 100, 0),
+                Address::new_4d(2, 5020, 1, 0),
+                (5020, 100),
+                (5020, 1),
+            ),
+        )
+    }
+
     #[test]
     fn parse_valid_address() {
         assert_eq!(Address::from_str("2:50/0"), Ok(Address::new_4d(2, 50, 0, 0)));
@@ -804,6 +1373,69 @@ mod test {
         assert_eq!(Address::from_str("a:b/c.d").unwrap_err(), InvalidFormat);
     }
 
+    #[test]
+    fn compare_addresses_with_domains() {
+        use std::collections::HashSet;
+
+        let a = Address::new_5d(2, 5020, 100, 1, "FidoNet");
+        let b = Address::new_5d(2, 5020, 100, 1, "fidonet");
+
+        assert_eq!(a, b);
+        assert_eq!(HashSet::from([a.clone(), b]).len(), 1);
+        assert_ne!(a, Address::new_4d(2, 5020, 100, 1));
+        assert_ne!(a, Address::new_5d(2, 5020, 100, 1, "othernet"));
+
+        assert!(a.same_4d(&Address::new_4d(2, 5020, 100, 1)));
+        assert!(a.same_3d(&Address::new_3d(2, 5020, 100)));
+        assert!(!a.same_4d(&Address::new_3d(2, 5020, 100)));
+        assert!(!a.same_3d(&Address::new_5d(2, 5020, 100, 0, "othernet")));
+
+        assert!(Address::new_4d(2, 5020, 100, 1) < a);
+        assert!(a < Address::new_5d(2, 5020, 100, 1, "othernet"));
+        assert!(Address::new_5d(2, 5020, 100, 1, "zzz") < Address::new_4d(2, 5020, 100, 2));
+
+        assert_eq!(a.to_string(), "2:5020/100.1@fidonet");
+        assert_eq!(Address::new_4d(2, 5020, 100, 0).to_string(), "2:5020/100");
+    }
+
+    #[test]
+    fn match_address_patterns() {
+        let matches = |p: &str, a: &str| {
+            AddressPattern::from_str(p)
+                .unwrap()
+                .matches(&Address::from_str(a).unwrap())
+        };
+
+        assert!(matches("*", "1:2/3.4@othernet"));
+        assert!(matches("2:*", "2:5020/100.1"));
+        assert!(!matches("2:*", "1:5020/100"));
+        assert!(matches("2:5020/*", "2:5020/100.1"));
+        assert!(!matches("2:5020/*", "2:5030/100"));
+        assert!(matches("2:5020/100", "2:5020/100"));
+        assert!(!matches("2:5020/100", "2:5020/100.1"));
+        assert!(matches("2:5020/100.*", "2:5020/100.1"));
+        assert!(matches("2:5020/100.1", "2:5020/100.1"));
+        assert!(!matches("2:5020/100.1", "2:5020/100.2"));
+
+        assert!(matches("*@FidoNet", "2:5020/100@fidonet"));
+        assert!(matches("*@fidonet", "2:5020/100"));
+        assert!(!matches("*@fidonet", "2:5020/100@othernet"));
+        assert!(matches("2:5020/*@*", "2:5020/100@othernet"));
+
+        assert_eq!(
+            AddressPattern::from_str("2").unwrap_err(),
+            ParseAddressError::InvalidFormat
+        );
+        assert_eq!(
+            AddressPattern::from_str("2:x/*").unwrap_err(),
+            ParseAddressError::InvalidFormat
+        );
+        assert_eq!(
+            AddressPattern::from_str("2:5020/99999").unwrap_err(),
+            ParseAddressError::Overflow
+        );
+    }
+
     #[test]
     fn parse_valid_msgid() {
         assert_eq!(
@@ -893,30 +1525,264 @@ mod test {
     #[test]
     fn parse_valid_ftn_datetime() {
         use chrono::NaiveDate;
+        use DateFormat::*;
+
+        let table = [
+            ("12 Dec 93  14:42:12", (1993, 12, 12, 14, 42, 12), Fts0001),
+            (" 3 Oct 07  23:00:29", (2007, 10, 3, 23, 0, 29), Fts0001),
+            ("31 Oct 09  23:01:04", (2009, 10, 31, 23, 1, 4), Fts0001),
+            ("01 Mar 20  01:43:10", (2020, 3, 1, 1, 43, 10), Fts0001),
+            ("Mon  2 Mar 20 14:05", (2020, 3, 2, 14, 5, 0), SeaDog),
+            ("Sun 15 Nov 98 09:41", (1998, 11, 15, 9, 41, 0), SeaDog),
+            ("Fri 28 Feb 20 14:00:18", (2020, 2, 28, 14, 0, 18), SeaDog),
+            ("12 Dec 93 14:42:12", (1993, 12, 12, 14, 42, 12), Variant),
+            ("12 Dec 93  14:42", (1993, 12, 12, 14, 42, 0), Variant),
+            ("01 Mar 2020  01:43:10", (2020, 3, 1, 1, 43, 10), Variant),
+            ("1 mar 2020 1:43", (2020, 3, 1, 1, 43, 0), Variant),
+            ("2020-03-01 01:43:10", (2020, 3, 1, 1, 43, 10), Iso8601),
+            ("2020-03-01T01:43:10", (2020, 3, 1, 1, 43, 10), Iso8601),
+            ("2020-03-01 01:43", (2020, 3, 1, 1, 43, 0), Iso8601),
+        ];
+
+        for (s, (y, mo, d, h, mi, sec), format) in table {
+            assert_eq!(
+                parse_ftn_datetime(s),
+                Ok((NaiveDate::from_ymd(y, mo, d).and_hms(h, mi, sec), format)),
+                "{s}"
+            );
+        }
+    }
 
+    #[test]
+    fn fail_on_invalid_ftn_datetime() {
+        use DateTimeError::*;
+
+        assert_eq!(parse_ftn_datetime("12 Dec 93  14;42;12").unwrap_err(), Format);
+        assert_eq!(parse_ftn_datetime("12 Dek 93  14:42:12").unwrap_err(), Format);
+        assert_eq!(parse_ftn_datetime("12 Dec 993  14:42:12").unwrap_err(), Format);
+        assert_eq!(parse_ftn_datetime("Mon 12 Dec").unwrap_err(), Format);
+        assert_eq!(parse_ftn_datetime("+1 Dec 93  14:42:12").unwrap_err(), Format);
+        assert_eq!(parse_ftn_datetime("").unwrap_err(), Format);
         assert_eq!(
-            parse_ftn_datetime("12 Dec 93  14:42:12"),
-            Ok(NaiveDate::from_ymd(1993, 12, 12).and_hms(14, 42, 12))
+            parse_ftn_datetime("31 Feb 20  14:42:12").unwrap_err(),
+            Date {
+                year: 2020,
+                month: 2,
+                day: 31
+            }
         );
         assert_eq!(
-            parse_ftn_datetime(" 3 Oct 07  23:00:29"),
-            Ok(NaiveDate::from_ymd(2007, 10, 03).and_hms(23, 00, 29))
+            parse_ftn_datetime("28 Feb 20  24:42:12").unwrap_err(),
+            Time {
+                hour: 24,
+                minute: 42,
+                second: 12
+            }
         );
+    }
+
+    #[test]
+    fn parse_valid_tzutc() {
+        use chrono::FixedOffset;
+
+        assert_eq!(parse_tzutc("0300"), FixedOffset::east_opt(3 * 3600));
+        assert_eq!(parse_tzutc("+0300"), FixedOffset::east_opt(3 * 3600));
+        assert_eq!(parse_tzutc("-0500"), FixedOffset::west_opt(5 * 3600));
+        assert_eq!(parse_tzutc("0545"), FixedOffset::east_opt(5 * 3600 + 45 * 60));
+        assert_eq!(parse_tzutc("-330 "), FixedOffset::west_opt(3 * 3600 + 30 * 60));
+        assert_eq!(parse_tzutc("0000"), FixedOffset::east_opt(0));
+
+        assert_eq!(parse_tzutc(""), None);
+        assert_eq!(parse_tzutc("03:00"), None);
+        assert_eq!(parse_tzutc("0370"), None);
+        assert_eq!(parse_tzutc("2500"), None);
+        assert_eq!(parse_tzutc("MSK"), None);
+    }
+
+    #[test]
+    fn posted_in_utc() {
+        use chrono::NaiveDate;
+
+        let mut msg = parse_body("Hello\r");
+        assert_eq!(msg.utc_offset(), None);
+        assert_eq!(msg.posted_utc(), None);
+
+        msg.kludges.tzutc = Some("-0130".to_string());
         assert_eq!(
-            parse_ftn_datetime("31 Oct 09  23:01:04"),
-            Ok(NaiveDate::from_ymd(2009, 10, 31).and_hms(23, 01, 04))
+            msg.posted_utc(),
+            Some(NaiveDate::from_ymd(2020, 2, 28).and_hms(15, 30, 18))
         );
+    }
+
+    #[test]
+    fn area_base_names() {
+        let base = |tag: &str| Area::Echomail(tag.to_string()).base_name();
+
+        assert_eq!(Area::Netmail.base_name().as_deref(), Some("netmail"));
+        assert_eq!(base("SU.GENERAL").as_deref(), Some("su.general"));
+
+        for tag in ["", ".quarantine", "../x", "A/B", "A\\B"] {
+            assert_eq!(base(tag), None, "{tag}");
+        }
+    }
+
+    #[test]
+    fn parse_valid_kludge() {
+        assert_eq!(Kludge::from("CHRS: CP866 2"), Kludge::new("CHRS", "CP866 2"));
+        assert_eq!(Kludge::from("CHRS:UTF-8 4"), Kludge::new("CHRS", "UTF-8 4"));
         assert_eq!(
-            parse_ftn_datetime("01 Mar 20  01:43:10"),
-            Ok(NaiveDate::from_ymd(2020, 03, 01).and_hms(01, 43, 10))
+            Kludge::from("INTL 2:5020/1 2:5020/100"),
+            Kludge::new("INTL", "2:5020/1 2:5020/100")
         );
+        assert_eq!(Kludge::from("NOECHO"), Kludge::new("NOECHO", ""));
     }
 
     #[test]
-    fn fail_on_invalid_ftn_datetime() {
-        use DateTimeError::*;
+    fn kludges_keep_order() {
+        let msg = parse_body(
+            "\u{1}INTL 2:5020/1 2:5020/100\n\u{1}MSGID: 2:5020/100 12345678\n\u{1}CHRS: CP866 2\nHello\n\u{1}Via 2:5020/100 @20200228.140018.UTC corona",
+        );
 
-        assert_eq!(parse_ftn_datetime("12 Dec 93 14:42:12").unwrap_err(), Format);
-        assert_eq!(parse_ftn_datetime("12 Dec 93  14;42;12").unwrap_err(), Format);
+        assert_eq!(
+            msg.kludges.lines.unwrap(),
+            vec![
+                Kludge::new("INTL", "2:5020/1 2:5020/100"),
+                Kludge::new("MSGID", "2:5020/100 12345678"),
+                Kludge::new("CHRS", "CP866 2"),
+                Kludge::new("Via", "2:5020/100 @20200228.140018.UTC corona"),
+            ]
+        );
+        assert_eq!(msg.body, "Hello");
+    }
+
+    #[test]
+    fn parse_valid_via() {
+        use chrono::NaiveDate;
+
+        assert_eq!(
+            Via::from_str("2:5020/1 @20200228.140018.UTC hpt/lnx 1.9.0-cur 2020-02-20"),
+            Ok(Via {
+                addr: Address::new_4d(2, 5020, 1, 0),
+                at: Some(NaiveDate::from_ymd(2020, 2, 28).and_hms(14, 0, 18)),
+                utc: true,
+                software: "hpt/lnx 1.9.0-cur 2020-02-20".to_string(),
+            })
+        );
+        assert_eq!(
+            Via::from_str("2:5020/1@fidonet @19950812.073152 Squish 1.11"),
+            Ok(Via {
+                addr: Address::full(2, 5020, 1, 0, Some("fidonet".to_string())),
+                at: Some(NaiveDate::from_ymd(1995, 8, 12).and_hms(7, 31, 52)),
+                utc: false,
+                software: "Squish 1.11".to_string(),
+            })
+        );
+        assert_eq!(
+            Via::from_str("FidoGate 2:5020/1, Fri Feb 28 2020 at 14:00 (1.0)"),
+            Ok(Via {
+                addr: Address::new_4d(2, 5020, 1, 0),
+                at: None,
+                utc: false,
+                software: "FidoGate Fri Feb 28 2020 at 14:00 (1.0)".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn fail_on_invalid_via() {
+        assert_eq!(Via::from_str("").unwrap_err(), ParseViaError::InvalidFormat);
+        assert_eq!(
+            Via::from_str("@20200228.140018.UTC hpt").unwrap_err(),
+            ParseViaError::InvalidFormat
+        );
+    }
+
+    #[test]
+    fn format_via() {
+        let via = Via::from_str("2:5020/1.5 @20200228.140018.UTC corona 0.1.0").unwrap();
+
+        assert_eq!(via.to_string(), "2:5020/1.5 @20200228.140018.UTC corona 0.1.0");
+    }
+
+    // packet from 2:5020/100.7 to 2:5020/1
+    fn point_hints(from: (u16, u16), to: (u16, u16)) -> AddressHints {
+        AddressHints::new(
+            Address::new_4d(2, 5020, 100, 7),
+            Address::new_4d(2, 5020, 1, 0),
+            from,
+            to,
+        )
+    }
+
+    #[test]
+    fn resolve_netmail_addresses() {
+        // no kludges at all, a point sends a 2+ packet on behalf of its boss
+        let msg = parse_with_hints("Hello", "Sysop", point_hints((5020, 100), (5020, 1)));
+        assert_eq!(msg.area, Area::Netmail);
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 100, 7));
+        assert_eq!(msg.to.addr, Address::new_4d(2, 5020, 1, 0));
+
+        // INTL changes zones, FMPT beats the packet header
+        let msg = parse_with_hints(
+            "\u{1}INTL 1:123/4 2:5020/100\n\u{1}FMPT 3\n\u{1}TOPT 5\nHello",
+            "Sysop",
+            point_hints((5020, 100), (5020, 1)),
+        );
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 100, 3));
+        assert_eq!(msg.to.addr, Address::new_4d(1, 123, 4, 5));
+
+        // transit netmail doesn't get the point of the packet sender
+        let msg = parse_with_hints(
+            "\u{1}INTL 2:5020/1 2:5030/1\nHello",
+            "Sysop",
+            point_hints((5030, 1), (5020, 1)),
+        );
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5030, 1, 0));
+        assert_eq!(msg.to.addr, Address::new_4d(2, 5020, 1, 0));
+    }
+
+    #[test]
+    fn resolve_echomail_addresses() {
+        let msg = parse_with_hints(
+            "AREA:TEST\n\u{1}MSGID: 2:5020/100.7@fidonet 12345678\nHello\n * Origin: Test (2:5020/100.1)",
+            "All",
+            point_hints((5020, 100), (5020, 1)),
+        );
+        assert_eq!(msg.area, Area::Echomail("TEST".to_string()));
+        assert_eq!(
+            msg.from.addr,
+            Address::full(2, 5020, 100, 7, Some("fidonet".to_string()))
+        );
+        assert_eq!(msg.to.addr, Address::empty());
+
+        // REPLYTO beats MSGID, REPLY gives TO
+        let msg = parse_with_hints(
+            "AREA:TEST\n\u{1}MSGID: 2:5020/100 12345678\n\u{1}REPLY: 2:5030/5 87654321\n\u{1}REPLYTO 2:5020/400 UUCP\nHello",
+            "John Doe",
+            point_hints((5020, 100), (5020, 1)),
+        );
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 400, 0));
+        assert_eq!(msg.to.addr, Address::new_4d(2, 5030, 5, 0));
+
+        // all three disagree: REPLYTO, then MSGID, then Origin
+        let text = "AREA:TEST\n\u{1}MSGID: 2:5020/200 12345678\n\u{1}REPLYTO 2:5020/300 UUCP\nHello\n * Origin: Test (2:5020/400)";
+        let msg = parse_with_hints(text, "All", point_hints((5020, 100), (5020, 1)));
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 300, 0));
+
+        let text = text.replace("\u{1}REPLYTO 2:5020/300 UUCP\n", "");
+        let msg = parse_with_hints(&text, "All", point_hints((5020, 100), (5020, 1)));
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 200, 0));
+
+        // Origin is the last resort
+        let msg = parse_with_hints(
+            "AREA:TEST\nHello\n * Origin: Test (2:5020/100.1)",
+            "All",
+            point_hints((5020, 100), (5020, 1)),
+        );
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 100, 1));
+
+        // otherwise the packet knows better
+        let msg = parse_with_hints("AREA:TEST\nHello", "All", point_hints((5020, 100), (5020, 1)));
+        assert_eq!(msg.from.addr, Address::new_4d(2, 5020, 100, 7));
     }
 }
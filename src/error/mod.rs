@@ -0,0 +1,143 @@
+use std::{fmt, io, path::PathBuf};
+
+use crate::cfg::ConfigError;
+use crate::core::{Address, DecodeError};
+use crate::ftn::{PackageError, ParseError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors of the whole crate, by what went wrong rather than where
+#[derive(Debug)]
+pub enum Error {
+    /// File system failure outside of packet reading
+    Io(io::Error),
+    /// Packet or bundle is damaged
+    Parse(ParseError),
+    /// Message text doesn't fit its charset
+    Decode(DecodeError),
+    /// Message base failure
+    Store(rusqlite::Error),
+    Config(ConfigError),
+    /// Mail which must not be accepted
+    Security(SecurityError),
+    /// Another run holds the lock, with its PID if it is known
+    Locked(Option<u32>),
+    /// Requested item, e.g. `netmail #5`, doesn't exist
+    NotFound(String),
+    /// Filesystem of `path` has `free` bytes left, less than `needed`
+    NoSpace {
+        path: PathBuf,
+        free: u64,
+        needed: u64,
+    },
+}
+
+impl Error {
+    /// Whether a filesystem is full or about to be
+    pub fn is_no_space(&self) -> bool {
+        match self {
+            Self::NoSpace { .. } => true,
+            Self::Io(e) => e.kind() == io::ErrorKind::StorageFull,
+            Self::Store(rusqlite::Error::SqliteFailure(e, _)) => e.code == rusqlite::ErrorCode::DiskFull,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SecurityError {
+    /// Link is not in the `inbound.allow` list
+    LinkNotAllowed(Address),
+    /// Packet password differs from the one of the link in config
+    WrongPassword(Address),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Parse(e) => e.fmt(f),
+            Self::Decode(e) => e.fmt(f),
+            Self::Store(e) => write!(f, "Message base: {e}"),
+            Self::Config(e) => e.fmt(f),
+            Self::Security(e) => e.fmt(f),
+            Self::Locked(Some(pid)) => write!(f, "Another run (process {pid}) is in progress"),
+            Self::Locked(None) => write!(f, "Another run is in progress"),
+            Self::NotFound(what) => write!(f, "There is no {what}"),
+            Self::NoSpace { path, free, needed } => write!(
+                f,
+                "Only {} MiB free on the filesystem of {:?}, {} MiB needed",
+                free >> 20,
+                path,
+                needed >> 20
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Parse(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            Self::Store(e) => Some(e),
+            Self::Config(e) => Some(e),
+            Self::Security(e) => Some(e),
+            Self::Locked(_) | Self::NotFound(_) | Self::NoSpace { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for SecurityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LinkNotAllowed(link) => write!(f, "packets from {link} are not allowed"),
+            Self::WrongPassword(link) => write!(f, "packet from {link} has a wrong password"),
+        }
+    }
+}
+
+impl std::error::Error for SecurityError {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<PackageError> for Error {
+    fn from(e: PackageError) -> Self {
+        Self::Parse(e.into())
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
+}
+
+impl From<SecurityError> for Error {
+    fn from(e: SecurityError) -> Self {
+        Self::Security(e)
+    }
+}
@@ -1,34 +1,34 @@
-use std::error::Error;
 use std::io::{Read, Seek};
-use zip::ZipArchive;
+use std::path::Path;
+use zip::{read::ZipFile, ZipArchive};
 
-use super::Package;
+use super::{Package, ParseError};
 
-pub struct Bundle {
-    pub packages: Vec<Package>,
+/// Archive of packets, which are read one by one
+pub struct Bundle<R: Read + Seek> {
+    arc: ZipArchive<R>,
 }
 
-impl Bundle {
-    pub fn read(data: impl Read + Seek) -> Result<Bundle, Box<dyn Error>> {
-        let mut packages = Vec::new();
-        let mut arc = ZipArchive::new(data)?;
+impl<R: Read + Seek> Bundle<R> {
+    pub fn read(data: R) -> Result<Self, ParseError> {
+        Ok(Self {
+            arc: ZipArchive::new(data)?,
+        })
+    }
 
-        for i in 0..arc.len() {
-            // println!("file index {}", i);
-            let file = arc
-                .by_index(i)
-                .unwrap_or_else(|_| panic!("Failed to get a file by_index({})", i));
+    pub fn file_count(&self) -> usize {
+        self.arc.len()
+    }
 
-            let path = file.sanitized_name();
+    /// Package stored at `index` or `None` if the file is not a `pkt`
+    pub fn package(&mut self, index: usize) -> Result<Option<Package<ZipFile<'_>>>, ParseError> {
+        let file = self.arc.by_index(index)?;
+        let path = Path::new(file.name());
 
-            if path.extension().map_or("", |x| x.to_str().unwrap_or("")) == "pkt" {
-                if let Some(_name) = path.to_str() {
-                    // println!("{}", name); // 5b1e8f04.pkt
-                    packages.push(Package::read(file)?);
-                }
-            }
+        if path.extension().map_or("", |x| x.to_str().unwrap_or("")) == "pkt" {
+            Ok(Some(Package::read(file)?))
+        } else {
+            Ok(None)
         }
-
-        Ok(Self { packages })
     }
 }
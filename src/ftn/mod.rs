@@ -1,5 +1,44 @@
+use std::{error::Error, fmt};
+
 mod bundle;
 mod pkt;
 
 pub use bundle::Bundle;
-pub use pkt::{Address, Package};
+pub use pkt::{Address, Header, HeaderKind, Message, Package, PackageError, User};
+
+/// Damaged packet, on its own or inside a bundle
+#[derive(Debug)]
+pub enum ParseError {
+    Package(PackageError),
+    Bundle(zip::result::ZipError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Package(e) => e.fmt(f),
+            Self::Bundle(e) => write!(f, "Bundle: {e}"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Package(e) => Some(e),
+            Self::Bundle(e) => Some(e),
+        }
+    }
+}
+
+impl From<PackageError> for ParseError {
+    fn from(e: PackageError) -> Self {
+        Self::Package(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ParseError {
+    fn from(e: zip::result::ZipError) -> Self {
+        Self::Bundle(e)
+    }
+}
@@ -2,21 +2,24 @@ use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use chrono::{NaiveDate, NaiveDateTime};
 use std::{
     error::Error,
-    fmt,
+    fmt, io,
     io::{BufRead, BufReader, Read},
 };
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Address {
     pub zone: u16,
     pub net: u16,
     pub node: u16,
     pub point: u16,
+    pub domain: Option<String>,
 }
 
+/// Packed message header carries only net/node, the rest is up to the message text
 #[derive(Debug)]
 pub struct User {
-    pub address: Address,
+    pub net: u16,
+    pub node: u16,
     pub name: Vec<u8>,
 }
 
@@ -30,28 +33,93 @@ pub struct Message {
     pub text: Vec<u8>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
-pub struct Package {
-    orig: Address,
-    dest: Address,
-    pub created: NaiveDateTime,
+/// Packet header layout
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum HeaderKind {
+    /// FTS-0001 stone age packet: no points, zones at QMail offsets
+    Type2,
+    /// FSC-0039 with a valid capability word
+    Type2Plus,
+    /// FSC-0048: a point sets orig net to -1 and puts its boss net into aux net
+    Type2N,
+    /// FSC-0045: points instead of a date, domains instead of the capability word
+    Type22,
+}
+
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub kind: HeaderKind,
+    pub orig: Address,
+    pub dest: Address,
+    /// Not available in type 2.2 headers
+    pub created: Option<NaiveDateTime>,
     password: String,
-    rate: u16,
-    ver: u16,
-    prod_code: u8,
-    serial_no: u8,
-    aux_net: u16,
-    cap_word: u16,
-    hi_product_code: u8,
-    minor_product_rev: u8,
-    pub messages: Vec<Message>,
 }
 
+/// Packet which reads its messages one at a time, as an iterator
+pub struct Package<R: Read> {
+    pub header: Header,
+    r: Tracked<R>,
+    max_message_size: u64,
+    lenient: bool,
+    /// Number of messages read so far
+    count: usize,
+    /// Offset of the message being read
+    start: u64,
+    damage: Vec<PackageError>,
+    done: bool,
+}
+
+/// Keeps the position in the packet for error reports
+struct Tracked<R> {
+    r: BufReader<R>,
+    pos: u64,
+}
+
+/// Offsets are counted from the start of the packet, message numbers start from 1
 #[derive(Debug)]
 pub enum PackageError {
-    InvalidDate { year: u16, month: u16, day: u16 },
-    InvalidTime { hour: u16, minute: u16, second: u16 },
+    InvalidDate {
+        year: u16,
+        month: u16,
+        day: u16,
+    },
+    InvalidTime {
+        hour: u16,
+        minute: u16,
+        second: u16,
+    },
+    /// Header password is not UTF-8
+    InvalidPassword,
+    /// Packet is shorter than its header
+    TruncatedHeader {
+        len: u64,
+    },
+    /// Packet ends inside `field`, which starts at `offset`
+    Truncated {
+        offset: u64,
+        message: usize,
+        field: &'static str,
+    },
+    /// Message at `offset` is larger than `limit` bytes
+    MessageTooLarge {
+        offset: u64,
+        message: usize,
+        limit: u64,
+    },
+    /// Packet ends after a message without the closing zero word
+    MissingTerminator {
+        offset: u64,
+    },
+    /// `len` bytes at `offset` are neither a message nor the closing zero word
+    TrailingGarbage {
+        offset: u64,
+        len: u64,
+    },
+    Io {
+        offset: u64,
+        source: io::Error,
+    },
 }
 
 impl PackageError {
@@ -66,199 +134,468 @@ impl PackageError {
 
 impl fmt::Display for PackageError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::InvalidDate { year, month, day } => write!(f, "Invalid date {year:04}-{month:02}-{day:02}"),
+            Self::InvalidTime { hour, minute, second } => write!(f, "Invalid time {hour:02}:{minute:02}:{second:02}"),
+            Self::InvalidPassword => write!(f, "Password is not valid UTF-8"),
+            Self::TruncatedHeader { len } => write!(f, "Packet is only {len} bytes long, header needs {HEADER_LEN}"),
+            Self::Truncated { offset, message, field } => {
+                write!(
+                    f,
+                    "Message {message}: {field} at byte {offset} is cut short by the end of packet"
+                )
+            }
+            Self::MessageTooLarge { offset, message, limit } => {
+                write!(f, "Message {message} at byte {offset} is larger than {limit} bytes")
+            }
+            Self::MissingTerminator { offset } => write!(f, "Packet ends at byte {offset} without terminator"),
+            Self::TrailingGarbage { offset, len } => write!(f, "{len} byte(s) of unknown data at byte {offset}"),
+            Self::Io { offset, source } => write!(f, "Read failed at byte {offset}: {source}"),
+        }
     }
 }
 
 impl Error for PackageError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        Some(self)
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
     }
 }
 
 const POSTED_DATE_LEN: usize = 19;
+const HEADER_LEN: usize = 58;
+
+/// FSC-0045 puts 2 into the baud rate field
+const TYPE_22_SUB_VERSION: u16 = 2;
+/// FSC-0039 capability word bit for type 2+ packets
+const CAP_TYPE_2_PLUS: u16 = 1;
+/// FSC-0048 orig net of a point
+const POINT_NET: u16 = 0xffff;
+
+impl Address {
+    fn new(zone: u16, net: u16, node: u16, point: u16, domain: Option<String>) -> Self {
+        Self {
+            zone,
+            net,
+            node,
+            point,
+            domain,
+        }
+    }
+}
 
-impl Package {
-    pub fn read(data: impl Read) -> Result<Package, Box<dyn Error>> {
-        let mut r = BufReader::new(data);
+impl Header {
+    /// Packet password, empty if there is none
+    pub fn password(&self) -> &str {
+        &self.password
+    }
 
-        let orig_node = r.read_u16::<LittleEndian>()?;
-        let dest_node = r.read_u16::<LittleEndian>()?;
+    fn read(r: &mut Tracked<impl Read>) -> Result<Header, PackageError> {
+        let mut hdr = [0u8; HEADER_LEN];
+        let len = r.fill(&mut hdr)?;
 
-        let year = r.read_u16::<LittleEndian>()?;
-        let month = r.read_u16::<LittleEndian>()? + 1;
-        let day = r.read_u16::<LittleEndian>()?;
-        let hour = r.read_u16::<LittleEndian>()?;
-        let minute = r.read_u16::<LittleEndian>()?;
-        let second = r.read_u16::<LittleEndian>()?;
+        if len < HEADER_LEN {
+            return Err(PackageError::TruncatedHeader { len: len as u64 });
+        }
 
-        let created = NaiveDate::from_ymd_opt(year.into(), month.into(), day.into())
-            .ok_or_else(|| Box::new(PackageError::date(year, month, day)))?
-            .and_hms_opt(hour.into(), minute.into(), second.into())
-            .ok_or_else(|| Box::new(PackageError::time(hour, minute, second)))?;
+        Self::parse(&hdr)
+    }
 
-        let rate = r.read_u16::<LittleEndian>()?;
-        let ver = r.read_u16::<LittleEndian>()?;
+    fn parse(hdr: &[u8; HEADER_LEN]) -> Result<Header, PackageError> {
+        // reads can't fail on a whole header
+        let io = |source| PackageError::Io { offset: 0, source };
+        let mut h = &hdr[..];
 
-        let orig_net = r.read_u16::<LittleEndian>()?;
-        let dest_net = r.read_u16::<LittleEndian>()?;
+        let orig_node = h.read_u16::<LittleEndian>().map_err(io)?;
+        let dest_node = h.read_u16::<LittleEndian>().map_err(io)?;
 
-        let prod_code = r.read_u8()?;
-        let serial_no = r.read_u8()?;
+        // either a date or (type 2.2) points followed by reserved bytes
+        let mut date = [0u16; 6];
+        h.read_u16_into::<LittleEndian>(&mut date).map_err(io)?;
 
-        let mut password = [0u8; 8];
-        r.read_exact(&mut password)?;
-        let password = String::from_utf8(password.into_iter().take_while(|x| x != &0).collect())?;
+        let rate = h.read_u16::<LittleEndian>().map_err(io)?;
+        // packet version, 2 in all the layouts
+        h.read_u16::<LittleEndian>().map_err(io)?;
 
-        let orig_zone = r.read_u16::<LittleEndian>()?;
-        let dest_zone = r.read_u16::<LittleEndian>()?;
+        let orig_net = h.read_u16::<LittleEndian>().map_err(io)?;
+        let dest_net = h.read_u16::<LittleEndian>().map_err(io)?;
 
-        let aux_net = r.read_u16::<LittleEndian>()?;
+        // product code and serial number (2.2: product revision)
+        h.read_u16::<LittleEndian>().map_err(io)?;
 
-        let _cap_word_copy = r.read_u16::<BigEndian>()?;
+        let mut password = [0u8; 8];
+        h.read_exact(&mut password).map_err(io)?;
+        let password = String::from_utf8(password.into_iter().take_while(|x| x != &0).collect())
+            .map_err(|_| PackageError::InvalidPassword)?;
 
-        let hi_product_code = r.read_u8()?;
-        let minor_product_rev = r.read_u8()?;
+        let qm_orig_zone = h.read_u16::<LittleEndian>().map_err(io)?;
+        let qm_dest_zone = h.read_u16::<LittleEndian>().map_err(io)?;
 
-        let cap_word = r.read_u16::<LittleEndian>()?;
+        let aux_net = h.read_u16::<LittleEndian>().map_err(io)?;
 
-        r.read_u32::<LittleEndian>()?; // zone info
+        let cap_word_copy = h.read_u16::<BigEndian>().map_err(io)?;
 
-        let orig_point = r.read_u16::<LittleEndian>()?;
-        let dest_point = r.read_u16::<LittleEndian>()?;
+        // high byte of the product code and minor revision
+        h.read_u16::<LittleEndian>().map_err(io)?;
 
-        r.read_u32::<LittleEndian>()?; // product specific data
+        let cap_word = h.read_u16::<LittleEndian>().map_err(io)?;
 
-        let mut messages = Vec::new();
+        let orig_zone = h.read_u16::<LittleEndian>().map_err(io)?;
+        let dest_zone = h.read_u16::<LittleEndian>().map_err(io)?;
 
-        while let Ok(w) = r.read_u16::<LittleEndian>() {
-            if w != 2 {
-                let mut extra_bytes = Vec::new();
+        let orig_point = h.read_u16::<LittleEndian>().map_err(io)?;
+        let dest_point = h.read_u16::<LittleEndian>().map_err(io)?;
 
-                match r.read_to_end(&mut extra_bytes) {
-                    Ok(0) => break, // there are no extra bytes
-                    Ok(size) => eprintln!("There are {:?} byte(s) of unknown data at the end of pkt file!", size), // TODO: PackageError(extra_bytes)
-                    Err(e) => return Err(Box::new(e)),
-                }
+        // type 2.2 keeps domains where the capability word is, so a valid one rules it out
+        let kind = if cap_word == cap_word_copy && cap_word & CAP_TYPE_2_PLUS != 0 {
+            if orig_net == POINT_NET && aux_net != 0 {
+                HeaderKind::Type2N
+            } else {
+                HeaderKind::Type2Plus
             }
+        } else if rate == TYPE_22_SUB_VERSION {
+            HeaderKind::Type22
+        } else {
+            HeaderKind::Type2
+        };
+
+        let created = if kind == HeaderKind::Type22 {
+            None
+        } else {
+            let [year, month, day, hour, minute, second] = date;
+            let month = month.saturating_add(1);
+
+            Some(
+                NaiveDate::from_ymd_opt(year.into(), month.into(), day.into())
+                    .ok_or_else(|| PackageError::date(year, month, day))?
+                    .and_hms_opt(hour.into(), minute.into(), second.into())
+                    .ok_or_else(|| PackageError::time(hour, minute, second))?,
+            )
+        };
+
+        fn domain(b: &[u8]) -> Option<String> {
+            let d: String = b.iter().take_while(|x| x != &&0).map(|x| *x as char).collect();
+
+            Some(d).filter(|x| !x.is_empty())
+        }
+
+        let (orig, dest) = match kind {
+            HeaderKind::Type2 => (
+                Address::new(qm_orig_zone, orig_net, orig_node, 0, None),
+                Address::new(qm_dest_zone, dest_net, dest_node, 0, None),
+            ),
+            HeaderKind::Type2Plus | HeaderKind::Type2N => {
+                let or_qm = |zone, qm_zone| if zone != 0 { zone } else { qm_zone };
+                let orig_net = if kind == HeaderKind::Type2N { aux_net } else { orig_net };
+
+                (
+                    Address::new(or_qm(orig_zone, qm_orig_zone), orig_net, orig_node, orig_point, None),
+                    Address::new(or_qm(dest_zone, qm_dest_zone), dest_net, dest_node, dest_point, None),
+                )
+            }
+            HeaderKind::Type22 => (
+                Address::new(qm_orig_zone, orig_net, orig_node, date[0], domain(&hdr[38..46])),
+                Address::new(qm_dest_zone, dest_net, dest_node, date[1], domain(&hdr[46..54])),
+            ),
+        };
+
+        Ok(Self {
+            kind,
+            orig,
+            dest,
+            created,
+            password,
+        })
+    }
+}
 
-            let from_node = r.read_u16::<LittleEndian>()?;
-            let to_node = r.read_u16::<LittleEndian>()?;
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.r.read(buf)?;
+        self.pos += len as u64;
 
-            let from_net = r.read_u16::<LittleEndian>()?;
-            let to_net = r.read_u16::<LittleEndian>()?;
+        Ok(len)
+    }
+}
 
-            let flags = r.read_u16::<LittleEndian>()?;
+impl<R: Read> BufRead for Tracked<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.r.fill_buf()
+    }
 
-            let _ = r.read_u16::<LittleEndian>()?;
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+        self.r.consume(amt);
+    }
+}
 
-            fn read_excl_zero(r: &mut BufReader<impl Read>, v: &mut Vec<u8>) -> std::io::Result<usize> {
-                let len = r.read_until(0, v)?;
+impl<R: Read> Tracked<R> {
+    fn io(&self, source: io::Error) -> PackageError {
+        PackageError::Io {
+            offset: self.pos,
+            source,
+        }
+    }
 
-                match v.last() {
-                    Some(&0) => {
-                        v.pop();
+    /// Reads `buf.len()` bytes or less at the end of packet
+    fn fill(&mut self, buf: &mut [u8]) -> Result<usize, PackageError> {
+        let mut len = 0;
 
-                        Ok(len - 1)
-                    }
-                    _ => Ok(len),
-                }
+        while len < buf.len() {
+            match self.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.io(e)),
             }
+        }
 
-            let mut posted = Vec::new();
-            let mut to_name = Vec::new();
-            let mut from_name = Vec::new();
-            let mut subj = Vec::new();
-            let mut text = Vec::new();
+        Ok(len)
+    }
 
-            let posted_len = read_excl_zero(&mut r, &mut posted)?;
+    /// Appends no more than `limit` bytes to `v`, stops after a zero which is not kept
+    fn read_until_zero(&mut self, v: &mut Vec<u8>, limit: u64) -> Result<bool, PackageError> {
+        let res = self.by_ref().take(limit).read_until(0, v);
+        res.map_err(|e| self.io(e))?;
 
-            if posted_len != POSTED_DATE_LEN {
-                eprintln!(
-                    "Warning: posted date length {} != {}. Actual value: {:02X?}",
-                    posted_len, POSTED_DATE_LEN, posted
-                );
+        if v.last() == Some(&0) {
+            v.pop();
 
-                posted.clear();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
 
-                for _ in posted_len..POSTED_DATE_LEN {
-                    let extra = r.read_u8()?;
+    fn skip_to_end(&mut self) -> Result<u64, PackageError> {
+        let res = io::copy(self, &mut io::sink());
+        res.map_err(|e| self.io(e))
+    }
+}
 
-                    if extra != 0 {
-                        to_name.push(extra); // oops, bring it back
-                        break;
-                    }
+impl<R: Read> Package<R> {
+    /// Reads the header only, messages are read on iteration
+    pub fn read(data: R) -> Result<Self, PackageError> {
+        let mut r = Tracked {
+            r: BufReader::new(data),
+            pos: 0,
+        };
+        let header = Header::read(&mut r)?;
+
+        Ok(Self {
+            header,
+            r,
+            max_message_size: u64::MAX,
+            lenient: false,
+            count: 0,
+            start: 0,
+            damage: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Messages larger than `size` bytes (all the fields together) fail with `PackageError::MessageTooLarge`
+    pub fn with_max_message_size(mut self, size: u64) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// In lenient mode errors end the iteration without being returned, see `damage`.
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Offset of the message returned by the last `next`
+    pub fn offset(&self) -> u64 {
+        self.start
+    }
+
+    /// Problems found in lenient mode
+    pub fn damage(&self) -> &[PackageError] {
+        &self.damage
+    }
+
+    fn read_message(&mut self) -> Result<Option<Message>, PackageError> {
+        let offset = self.start;
+        let message = self.count + 1;
+
+        let mut w = [0u8; 2];
+
+        match self.r.fill(&mut w)? {
+            0 => return Err(PackageError::MissingTerminator { offset }),
+            1 => return Err(PackageError::TrailingGarbage { offset, len: 1 }),
+            _ => {}
+        }
+
+        match u16::from_le_bytes(w) {
+            0 => {
+                let len = self.r.skip_to_end()?;
+
+                if len != 0 {
+                    return Err(PackageError::TrailingGarbage {
+                        offset: offset + 2,
+                        len,
+                    });
                 }
+
+                return Ok(None);
+            }
+            2 => {}
+            _ => {
+                let len = self.r.skip_to_end()?;
+
+                return Err(PackageError::TrailingGarbage { offset, len: len + 2 });
             }
+        }
+
+        let mut hdr = [0u8; 12];
 
-            read_excl_zero(&mut r, &mut to_name)?;
-            read_excl_zero(&mut r, &mut from_name)?;
-            read_excl_zero(&mut r, &mut subj)?;
-            read_excl_zero(&mut r, &mut text)?;
-
-            messages.push(Message {
-                posted,
-                from: User {
-                    address: Address {
-                        zone: orig_zone,
-                        net: from_net,
-                        node: from_node,
-                        point: 0,
-                    },
-                    name: from_name,
-                },
-                to: User {
-                    address: Address {
-                        zone: dest_zone,
-                        net: to_net,
-                        node: to_node,
-                        point: 0,
-                    },
-                    name: to_name,
-                },
-                flags,
-                subj,
-                text,
+        if self.r.fill(&mut hdr)? != hdr.len() {
+            return Err(PackageError::Truncated {
+                offset: offset + 2,
+                message,
+                field: "header",
             });
         }
 
-        Ok(Self {
-            orig: Address {
-                zone: orig_zone,
-                net: orig_net,
-                node: orig_node,
-                point: orig_point,
+        let word = |i: usize| u16::from_le_bytes([hdr[i], hdr[i + 1]]);
+
+        let from_node = word(0);
+        let to_node = word(2);
+
+        let from_net = word(4);
+        let to_net = word(6);
+
+        let flags = word(8);
+
+        let mut budget = self.max_message_size;
+
+        let mut posted = Vec::new();
+        let mut to_name = Vec::new();
+        let mut from_name = Vec::new();
+        let mut subj = Vec::new();
+        let mut text = Vec::new();
+
+        self.read_field(&mut posted, "date", &mut budget)?;
+
+        // a shorter date may still be padded with zeros up to the fixed field length
+        if posted.len() < POSTED_DATE_LEN {
+            for _ in posted.len()..POSTED_DATE_LEN {
+                let at = self.r.pos;
+                let mut extra = [0u8];
+
+                if self.r.fill(&mut extra)? == 0 {
+                    return Err(PackageError::Truncated {
+                        offset: at,
+                        message,
+                        field: "date",
+                    });
+                }
+
+                if extra[0] != 0 {
+                    to_name.push(extra[0]); // oops, bring it back
+                    break;
+                }
+            }
+        }
+
+        // names and subjects longer than FTS-0001 allows are common, they are read in full
+        self.read_field(&mut to_name, "to", &mut budget)?;
+        self.read_field(&mut from_name, "from", &mut budget)?;
+        self.read_field(&mut subj, "subject", &mut budget)?;
+        self.read_field(&mut text, "text", &mut budget)?;
+
+        Ok(Some(Message {
+            posted,
+            from: User {
+                net: from_net,
+                node: from_node,
+                name: from_name,
             },
-            dest: Address {
-                zone: dest_zone,
-                net: dest_net,
-                node: dest_node,
-                point: dest_point,
+            to: User {
+                net: to_net,
+                node: to_node,
+                name: to_name,
             },
-            created,
-            password,
-            rate,
-            ver,
-            prod_code,
-            serial_no,
-            aux_net,
-            cap_word,
-            hi_product_code,
-            minor_product_rev,
-            messages,
-        })
+            flags,
+            subj,
+            text,
+        }))
+    }
+
+    /// Reads a zero terminated field, `budget` is what is left of the message size
+    fn read_field(&mut self, v: &mut Vec<u8>, field: &'static str, budget: &mut u64) -> Result<(), PackageError> {
+        let offset = self.r.pos;
+        let message = self.count + 1;
+
+        let terminated = self.r.read_until_zero(v, *budget)?;
+        let len = self.r.pos - offset;
+        *budget -= len;
+
+        if terminated {
+            Ok(())
+        } else if *budget > 0 {
+            Err(PackageError::Truncated { offset, message, field })
+        } else {
+            Err(PackageError::MessageTooLarge {
+                offset: self.start,
+                message,
+                limit: self.max_message_size,
+            })
+        }
+    }
+}
+
+impl<R: Read> Iterator for Package<R> {
+    type Item = Result<Message, PackageError>;
+
+    /// Stops after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        self.start = self.r.pos;
+
+        match self.read_message() {
+            Ok(Some(m)) => {
+                self.count += 1;
+                Some(Ok(m))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) if self.lenient => {
+                self.done = true;
+                self.damage.push(e);
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Package;
-    use std::io::Cursor;
+    use super::{HeaderKind, Package, PackageError};
+    use std::{fs, io::Cursor, path::Path};
 
     fn create_pkt() -> Vec<u8> {
+        create_pkt_with(add_header)
+    }
+
+    /// Packet of one message with the header written by `header`
+    fn create_pkt_with(header: fn(&mut Vec<u8>)) -> Vec<u8> {
         let mut data = Vec::new();
 
-        add_header(&mut data);
+        header(&mut data);
         add_message(&mut data);
         end_message(&mut data);
 
@@ -303,7 +640,67 @@ mod test {
 
     const PROD_DATA: u32 = 0;
 
+    /// Type 2+ header (FSC-0039)
     fn add_header(data: &mut Vec<u8>) {
+        add_header_2plus(data, RATE);
+    }
+
+    fn add_header_2plus(data: &mut Vec<u8>, rate: u16) {
+        add_header_start(data, ORIG_NET, rate);
+
+        data.extend_from_slice(&AUX_NET.to_le_bytes());
+
+        add_header_end(data);
+    }
+
+    /// Type 2 header (FTS-0001), there is nothing after the zones
+    fn add_header_2(data: &mut Vec<u8>) {
+        add_header_start(data, ORIG_NET, RATE);
+
+        data.extend_from_slice(&[0; 20]);
+    }
+
+    /// Type 2N header (FSC-0048), the net of a point is in the aux net
+    fn add_header_2n(data: &mut Vec<u8>) {
+        add_header_start(data, 0xffff, RATE);
+
+        data.extend_from_slice(&ORIG_NET.to_le_bytes());
+
+        add_header_end(data);
+    }
+
+    /// Type 2.2 header (FSC-0045), points and domains instead of the date
+    fn add_header_22(data: &mut Vec<u8>) {
+        data.extend_from_slice(&ORIG_NODE.to_le_bytes());
+        data.extend_from_slice(&DEST_NODE.to_le_bytes());
+
+        data.extend_from_slice(&ORIG_POINT.to_le_bytes());
+        data.extend_from_slice(&DEST_POINT.to_le_bytes());
+
+        data.extend_from_slice(&[0; 8]); // reserved
+        data.extend_from_slice(&2u16.to_le_bytes()); // sub-version
+
+        data.extend_from_slice(&VER.to_le_bytes());
+
+        data.extend_from_slice(&ORIG_NET.to_le_bytes());
+        data.extend_from_slice(&DEST_NET.to_le_bytes());
+
+        data.push(PROD_CODE);
+        data.push(SERIAL_NO);
+
+        data.extend_from_slice(PASSWORD.as_bytes());
+
+        data.extend_from_slice(&ORIG_ZONE.to_le_bytes());
+        data.extend_from_slice(&DEST_ZONE.to_le_bytes());
+
+        data.extend_from_slice(b"fidonet\0");
+        data.extend_from_slice(b"othnet\0\0");
+
+        data.extend_from_slice(&PROD_DATA.to_le_bytes());
+    }
+
+    /// Fields the types 2, 2+ and 2N share, up to the zones
+    fn add_header_start(data: &mut Vec<u8>, orig_net: u16, rate: u16) {
         data.extend_from_slice(&ORIG_NODE.to_le_bytes());
         data.extend_from_slice(&DEST_NODE.to_le_bytes());
 
@@ -314,10 +711,10 @@ mod test {
         data.extend_from_slice(&DT_MINUTE.to_le_bytes());
         data.extend_from_slice(&DT_SECOND.to_le_bytes());
 
-        data.extend_from_slice(&RATE.to_le_bytes());
+        data.extend_from_slice(&rate.to_le_bytes());
         data.extend_from_slice(&VER.to_le_bytes());
 
-        data.extend_from_slice(&ORIG_NET.to_le_bytes());
+        data.extend_from_slice(&orig_net.to_le_bytes());
         data.extend_from_slice(&DEST_NET.to_le_bytes());
 
         data.push(PROD_CODE);
@@ -327,9 +724,10 @@ mod test {
 
         data.extend_from_slice(&ORIG_ZONE.to_le_bytes());
         data.extend_from_slice(&DEST_ZONE.to_le_bytes());
+    }
 
-        data.extend_from_slice(&AUX_NET.to_le_bytes());
-
+    /// Fields the types 2+ and 2N share after the aux net
+    fn add_header_end(data: &mut Vec<u8>) {
         data.extend_from_slice(&CAP_WORD_X.to_le_bytes());
 
         data.push(HIGH_PROD_CODE);
@@ -375,9 +773,281 @@ mod test {
         let mem = Cursor::new(create_pkt());
         let pkg = Package::read(mem).unwrap();
 
-        assert_eq!(pkg.orig.zone, ORIG_ZONE);
-        assert_eq!(pkg.orig.net, ORIG_NET);
-        assert_eq!(pkg.orig.node, ORIG_NODE);
-        assert_eq!(pkg.orig.point, ORIG_POINT);
+        assert_eq!(pkg.header.orig.zone, ORIG_ZONE);
+        assert_eq!(pkg.header.orig.net, ORIG_NET);
+        assert_eq!(pkg.header.orig.node, ORIG_NODE);
+        assert_eq!(pkg.header.orig.point, ORIG_POINT);
+    }
+
+    #[test]
+    fn pkg_type_2plus() {
+        let pkg = Package::read(Cursor::new(create_pkt())).unwrap();
+
+        assert_eq!(pkg.header.kind, HeaderKind::Type2Plus);
+        assert_eq!(pkg.header.dest.zone, DEST_ZONE);
+        assert_eq!(pkg.header.dest.net, DEST_NET);
+        assert_eq!(pkg.header.dest.node, DEST_NODE);
+        assert_eq!(pkg.header.dest.point, DEST_POINT);
+        assert!(pkg.header.created.is_some());
+        assert_eq!(pkg.count(), 1);
+    }
+
+    #[test]
+    fn pkg_type_2plus_at_2_baud() {
+        // the baud rate of type 2.2 doesn't hide a valid capability word
+        let pkg = Package::read(Cursor::new(create_pkt_with(|data| add_header_2plus(data, 2)))).unwrap();
+
+        assert_eq!(pkg.header.kind, HeaderKind::Type2Plus);
+        assert_eq!(pkg.header.orig.zone, ORIG_ZONE);
+        assert_eq!(pkg.header.orig.point, ORIG_POINT);
+        assert_eq!(pkg.header.orig.domain, None);
+        assert!(pkg.header.created.is_some());
+    }
+
+    #[test]
+    fn pkg_type_2() {
+        let pkg = Package::read(Cursor::new(create_pkt_with(add_header_2))).unwrap();
+
+        assert_eq!(pkg.header.kind, HeaderKind::Type2);
+        assert_eq!(pkg.header.orig.zone, ORIG_ZONE);
+        assert_eq!(pkg.header.orig.net, ORIG_NET);
+        assert_eq!(pkg.header.orig.point, 0);
+        assert_eq!(pkg.header.dest.zone, DEST_ZONE);
+        assert_eq!(pkg.header.dest.point, 0);
+        assert_eq!(pkg.count(), 1);
+    }
+
+    #[test]
+    fn pkg_type_2n() {
+        let pkg = Package::read(Cursor::new(create_pkt_with(add_header_2n))).unwrap();
+
+        assert_eq!(pkg.header.kind, HeaderKind::Type2N);
+        assert_eq!(pkg.header.orig.zone, ORIG_ZONE);
+        assert_eq!(pkg.header.orig.net, ORIG_NET);
+        assert_eq!(pkg.header.orig.node, ORIG_NODE);
+        assert_eq!(pkg.header.orig.point, ORIG_POINT);
+        assert_eq!(pkg.count(), 1);
+    }
+
+    #[test]
+    fn pkg_type_22() {
+        let pkg = Package::read(Cursor::new(create_pkt_with(add_header_22))).unwrap();
+
+        assert_eq!(pkg.header.kind, HeaderKind::Type22);
+        assert_eq!(pkg.header.created, None);
+        assert_eq!(pkg.header.orig.zone, ORIG_ZONE);
+        assert_eq!(pkg.header.orig.net, ORIG_NET);
+        assert_eq!(pkg.header.orig.node, ORIG_NODE);
+        assert_eq!(pkg.header.orig.point, ORIG_POINT);
+        assert_eq!(pkg.header.orig.domain.as_deref(), Some("fidonet"));
+        assert_eq!(pkg.header.dest.zone, DEST_ZONE);
+        assert_eq!(pkg.header.dest.point, DEST_POINT);
+        assert_eq!(pkg.header.dest.domain.as_deref(), Some("othnet"));
+        assert_eq!(pkg.count(), 1);
+    }
+
+    #[test]
+    fn pkg_messages_are_lazy() {
+        let mut data = create_pkt();
+        let end = data.len() - 2;
+        data.truncate(end);
+        add_message(&mut data);
+        data.extend_from_slice(&[2, 0, 1]); // truncated message
+
+        let mut pkg = Package::read(Cursor::new(data)).unwrap();
+
+        assert_eq!(pkg.next().unwrap().unwrap().subj, b"Ping");
+        assert_eq!(pkg.next().unwrap().unwrap().text, b"Pong");
+        assert!(pkg.next().unwrap().is_err());
+        assert!(pkg.next().is_none());
+    }
+
+    #[test]
+    fn pkg_max_message_size() {
+        let mut pkg = Package::read(Cursor::new(create_pkt()))
+            .unwrap()
+            .with_max_message_size(32);
+
+        let err = pkg.next().unwrap().unwrap_err();
+
+        assert_eq!(err.to_string(), "Message 1 at byte 58 is larger than 32 bytes");
+        assert!(pkg.next().is_none());
+
+        let mut pkg = Package::read(Cursor::new(create_pkt()))
+            .unwrap()
+            .with_max_message_size(64);
+
+        assert!(pkg.next().unwrap().is_ok());
+    }
+
+    #[test]
+    fn pkg_truncated_message() {
+        let mut data = create_pkt();
+        let end = data.len() - 5;
+        data.truncate(end); // "Pong" without zero and terminator
+
+        let mut pkg = Package::read(Cursor::new(data)).unwrap();
+
+        match pkg.next().unwrap() {
+            Err(PackageError::Truncated { offset, message, field }) => {
+                assert_eq!((offset, message, field), (110, 1, "text"));
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+
+    #[test]
+    fn pkg_missing_terminator() {
+        let mut data = create_pkt();
+        let end = data.len() - 2;
+        data.truncate(end);
+
+        let mut pkg = Package::read(Cursor::new(data)).unwrap();
+
+        assert!(pkg.next().unwrap().is_ok());
+        assert!(matches!(
+            pkg.next(),
+            Some(Err(PackageError::MissingTerminator { offset: 115 }))
+        ));
+    }
+
+    #[test]
+    fn pkg_trailing_garbage() {
+        let mut data = create_pkt();
+        data.extend_from_slice(b"junk");
+
+        let mut pkg = Package::read(Cursor::new(data)).unwrap();
+
+        assert!(pkg.next().unwrap().is_ok());
+        assert_eq!(
+            pkg.next().unwrap().unwrap_err().to_string(),
+            "4 byte(s) of unknown data at byte 117"
+        );
+    }
+
+    #[test]
+    fn pkg_long_fields() {
+        let mut data = Vec::new();
+        add_header(&mut data);
+        add_message(&mut data);
+        let to = data.len() - 23;
+        data.splice(to..to + 3, [b'x'; 40]);
+        end_message(&mut data);
+
+        let mut pkg = Package::read(Cursor::new(data)).unwrap();
+
+        assert_eq!(pkg.next().unwrap().unwrap().to.name.len(), 40);
+        assert!(pkg.next().is_none());
+        assert!(pkg.damage().is_empty());
+    }
+
+    #[test]
+    fn pkg_lenient_keeps_messages() {
+        let mut data = create_pkt();
+        let end = data.len() - 2;
+        data.truncate(end);
+        add_message(&mut data);
+        data.extend_from_slice(&[2, 0, 1]);
+
+        let mut pkg = Package::read(Cursor::new(data)).unwrap().with_lenient(true);
+
+        assert_eq!(pkg.by_ref().filter(|m| m.is_ok()).count(), 2);
+        assert!(matches!(
+            pkg.damage(),
+            [PackageError::Truncated {
+                offset: 174,
+                message: 3,
+                ..
+            }]
+        ));
+    }
+
+    /// Packets in `tests/corpus` with the number of messages read from them and the error, if any
+    const CORPUS: &[(&str, usize, Option<&str>)] = &[
+        ("capword_copy_unswapped.pkt", 1, None),
+        ("ctrl_z_padding.pkt", 1, Some("41 byte(s) of unknown data at byte 343")),
+        ("date_long.pkt", 1, None),
+        ("date_non_ascii.pkt", 1, None),
+        ("date_short_padded.pkt", 1, None),
+        ("date_short_unpadded.pkt", 1, None),
+        ("date_utf8.pkt", 1, None),
+        ("date_year_100.pkt", 1, None),
+        ("embedded_nul.pkt", 1, Some("58 byte(s) of unknown data at byte 150")),
+        ("empty_text.pkt", 1, None),
+        ("header_bad_month.pkt", 0, Some("Invalid date 2020-65535-28")),
+        ("header_zero_date.pkt", 0, Some("Invalid date 0000-01-00")),
+        ("hub_mixed_areas.pkt", 3, None),
+        ("lf_newlines.pkt", 1, None),
+        ("long_to_name.pkt", 1, None),
+        (
+            "no_terminator.pkt",
+            2,
+            Some("Packet ends at byte 352 without terminator"),
+        ),
+        ("point_2n_netmail.pkt", 1, None),
+        ("trailing_garbage.pkt", 1, Some("3 byte(s) of unknown data at byte 207")),
+        (
+            "truncated_header.pkt",
+            0,
+            Some("Packet is only 40 bytes long, header needs 58"),
+        ),
+        (
+            "truncated_text.pkt",
+            1,
+            Some("Message 2: text at byte 257 is cut short by the end of packet"),
+        ),
+        ("type22.pkt", 1, None),
+    ];
+
+    #[test]
+    fn corpus_regressions() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".pkt"))
+            .collect();
+        names.sort();
+
+        assert_eq!(names, CORPUS.iter().map(|(name, ..)| *name).collect::<Vec<_>>());
+
+        for (name, count, error) in CORPUS {
+            let (read, err) = match Package::read(Cursor::new(fs::read(dir.join(name)).unwrap())) {
+                Ok(mut pkg) => {
+                    let mut read = 0;
+                    let mut err = None;
+
+                    while let Some(m) = pkg.next() {
+                        match m {
+                            Ok(m) => {
+                                crate::core::message_from(&pkg.header, m, |_| false).unwrap();
+                                read += 1;
+                            }
+                            Err(e) => err = Some(e.to_string()),
+                        }
+                    }
+
+                    (read, err)
+                }
+                Err(e) => (0, Some(e.to_string())),
+            };
+
+            assert_eq!((read, err.as_deref()), (*count, *error), "{}", name);
+        }
+    }
+
+    #[test]
+    fn corpus_header_quirks() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+        let header = |name| Package::read(fs::File::open(dir.join(name)).unwrap()).unwrap().header;
+
+        // a capability word which doesn't match its copy is no proof of type 2+
+        let h = header("capword_copy_unswapped.pkt");
+        assert_eq!((h.kind, h.orig.zone, h.orig.point), (HeaderKind::Type2, 2, 0));
+
+        let h = header("point_2n_netmail.pkt");
+        assert_eq!(h.kind, HeaderKind::Type2N);
+        assert_eq!((h.orig.net, h.orig.node, h.orig.point), (9999, 1, 7));
     }
 }
@@ -0,0 +1,39 @@
+//! Corona is an echomail processor / netmail tracker for FidoNet Technology Network (FTN).
+//!
+//! - [`ftn`] reads packets (`*.pkt`) and bundles as they come over the wire
+//! - [`core`] turns packed messages into [`core::Message`]: addresses, kludges, body, dates
+//! - [`store`] keeps messages in SQLite, one [`store::MessageBase`] per area
+//! - [`tosser`] does all of the above for an inbound directory described by [`cfg::Config`]
+//! - `watch` (Linux) tosses as soon as files arrive
+//!
+//! ```
+//! use corona::{core, ftn::Package};
+//! use std::fs::File;
+//!
+//! let mut pkg = Package::read(File::open("tests/corpus/lf_newlines.pkt")?)?;
+//!
+//! while let Some(m) = pkg.next() {
+//!     let msg = core::message_from(&pkg.header, m?, |_| false)?;
+//!
+//!     assert_eq!(msg.area, core::Area::Echomail("TEST.ECHO".to_string()));
+//!     assert_eq!(msg.from.name, "John Doe");
+//!     assert_eq!(msg.from.addr.to_string(), "2:5020/100");
+//! }
+//! # Ok::<(), corona::Error>(())
+//! ```
+
+pub mod badmail;
+pub mod cfg;
+pub mod core;
+mod error;
+pub mod ftn;
+pub mod lock;
+pub mod logging;
+pub mod netmail;
+pub mod store;
+pub mod tosser;
+#[cfg(target_os = "linux")]
+pub mod watch;
+
+pub use cfg::Config;
+pub use error::{Error, Result, SecurityError};
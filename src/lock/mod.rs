@@ -0,0 +1,92 @@
+use fs2::FileExt;
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cfg::{Config, ConfigError};
+use crate::error::{Error, Result};
+
+/// How often a busy lock is tried again while waiting
+const POLL: Duration = Duration::from_millis(500);
+
+/// Advisory lock of a semaphore file which holds the PID of its owner.
+/// The OS releases it when the owner dies, so a PID left in the file is a stale lock.
+///
+/// ```
+/// use corona::lock::Lock;
+/// use std::time::Duration;
+///
+/// let path = std::env::temp_dir().join("corona-doc.lock");
+/// let lock = Lock::acquire(&path, Duration::ZERO)?;
+///
+/// assert!(Lock::acquire(&path, Duration::ZERO).is_err());
+/// drop(lock);
+/// assert!(Lock::acquire(&path, Duration::ZERO).is_ok());
+/// # Ok::<(), corona::Error>(())
+/// ```
+pub struct Lock {
+    file: File,
+}
+
+impl Lock {
+    /// Lock of runs which change mail (toss, and scan or pack later on), so that they never overlap
+    pub fn for_config(config: &Config) -> Result<Self> {
+        let path = config
+            .lock_path()
+            .ok_or_else(|| ConfigError::Invalid("msgbase path is not set".to_string()))?;
+
+        Self::acquire(&path, config.lock_wait())
+    }
+
+    /// Waits up to `wait` for the owner to finish, fails with `Error::Locked` after that
+    pub fn acquire(path: &Path, wait: Duration) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let start = Instant::now();
+
+        let busy = fs2::lock_contended_error().kind();
+
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => break,
+                Err(e) if e.kind() == busy && start.elapsed() < wait => thread::sleep(POLL),
+                Err(e) if e.kind() == busy => return Err(Error::Locked(owner(&mut file))),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let Some(pid) = owner(&mut file) {
+            warn!("Removing stale lock of process {pid} from \"{}\"", path.display());
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        Ok(Self { file })
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // the file is kept, removing it would let two runs lock different files of the same name
+        let _ = self.file.set_len(0);
+    }
+}
+
+fn owner(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+
+    file.rewind().ok()?;
+    file.read_to_string(&mut pid).ok()?;
+
+    pid.trim().parse().ok()
+}
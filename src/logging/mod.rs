@@ -0,0 +1,264 @@
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use crate::cfg::{Config, LogFormat};
+use crate::error::Result;
+
+static LOGGER: Logger = Logger {
+    inner: RwLock::new(None),
+};
+
+/// Sends the `log` macros of the crate to the console and the log file set in `[log]` of `config`.
+/// Called again, e.g. after the config is reloaded, it replaces the settings.
+///
+/// Errors and warnings go to stderr, the rest to stdout. Per-module `targets` set the levels
+/// of the log file only, the console has one level for all modules. Lines of the log file
+/// carry the time and the level, in the format of other FTN software with `format = "ftn"`:
+///
+/// ```text
+/// + 18 Oct 17:39:15 [4242] tossing "/var/spool/ftn/inbound/0000abcd.pkt"
+/// ? 18 Oct 17:39:15 [4242] packet from 2:5020/100 has a wrong password
+/// ```
+pub fn init(config: &Config) -> Result<()> {
+    let settings = config.log.as_ref();
+    let console = config.console_level();
+
+    let file = match settings.and_then(|l| Some((l, l.file.as_ref()?))) {
+        Some((l, path)) => {
+            let mut targets: Vec<_> = l.targets().collect();
+            // the longest module path wins
+            targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+
+            Some(FileSink {
+                level: l.file_level(),
+                targets,
+                format: l.format.unwrap_or_default(),
+                file: Mutex::new(LogFile::open(Path::new(path), l.max_size(), l.keep())?),
+            })
+        }
+        None => None,
+    };
+
+    let max = file
+        .iter()
+        .flat_map(|f| f.targets.iter().map(|(_, l)| *l).chain([f.level]))
+        .fold(console, Ord::max);
+
+    *LOGGER.inner.write().unwrap_or_else(|e| e.into_inner()) = Some(Inner { console, file });
+
+    // fails if it is set already, which is us
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(max);
+
+    Ok(())
+}
+
+struct Logger {
+    inner: RwLock<Option<Inner>>,
+}
+
+struct Inner {
+    console: LevelFilter,
+    file: Option<FileSink>,
+}
+
+struct FileSink {
+    level: LevelFilter,
+    /// Levels by module path prefix, longest first
+    targets: Vec<(String, LevelFilter)>,
+    format: LogFormat,
+    file: Mutex<LogFile>,
+}
+
+impl FileSink {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = self
+            .targets
+            .iter()
+            .find(|(target, _)| within(metadata.target(), target))
+            .map_or(self.level, |(_, l)| *l);
+
+        metadata.level() <= level
+    }
+
+    fn line(&self, record: &Record) -> String {
+        let now = Local::now();
+
+        match self.format {
+            LogFormat::Plain => format!(
+                "{} {:<5} [{}] {}\n",
+                now.format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            LogFormat::Ftn => {
+                let mark = match record.level() {
+                    Level::Error => '!',
+                    Level::Warn => '?',
+                    Level::Info => '+',
+                    Level::Debug => '-',
+                    Level::Trace => ' ',
+                };
+
+                format!(
+                    "{} {} [{}] {}\n",
+                    mark,
+                    now.format("%d %b %H:%M:%S"),
+                    std::process::id(),
+                    record.args()
+                )
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match &*self.inner.read().unwrap_or_else(|e| e.into_inner()) {
+            Some(inner) => {
+                metadata.level() <= inner.console || inner.file.as_ref().is_some_and(|f| f.enabled(metadata))
+            }
+            None => false,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let guard = self.inner.read().unwrap_or_else(|e| e.into_inner());
+
+        let Some(inner) = &*guard else {
+            return;
+        };
+
+        if record.level() <= inner.console {
+            match record.level() {
+                Level::Error | Level::Warn => eprintln!("{}", record.args()),
+                _ => println!("{}", record.args()),
+            }
+        }
+
+        if let Some(sink) = inner.file.as_ref().filter(|f| f.enabled(record.metadata())) {
+            let line = sink.line(record);
+            let mut file = sink.file.lock().unwrap_or_else(|e| e.into_inner());
+
+            if let Err(e) = file.write(line.as_bytes()) {
+                eprintln!("Cannot write to the log file {}, reason: {e}", file.path.display());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let guard = self.inner.read().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(sink) = guard.as_ref().and_then(|i| i.file.as_ref()) {
+            let _ = sink.file.lock().unwrap_or_else(|e| e.into_inner()).file.flush();
+        }
+    }
+}
+
+/// Whether `module` is `target` or one of its submodules
+fn within(module: &str, target: &str) -> bool {
+    module
+        .strip_prefix(target)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Log file which is renamed to `<path>.1` once it grows past `max_size`, older ones shift
+/// up to `<path>.<keep>`
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl LogFile {
+    fn open(path: &Path, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.as_os_str().to_owned();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                if numbered(n).exists() {
+                    fs::rename(numbered(n), numbered(n + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, numbered(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{within, LogFile};
+    use std::fs;
+
+    #[test]
+    fn match_targets() {
+        assert!(within("corona::store", "corona::store"));
+        assert!(within("corona::store::journal", "corona::store"));
+        assert!(within("corona::store", "corona"));
+        assert!(!within("corona::storex", "corona::store"));
+        assert!(!within("corona", "corona::store"));
+    }
+
+    #[test]
+    fn rotate_log_files() {
+        let dir = std::env::temp_dir().join(format!("corona-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("corona.log");
+
+        let mut log = LogFile::open(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(dir.join("corona.log.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(dir.join("corona.log.2")).unwrap(), "second\n");
+        assert!(!dir.join("corona.log.3").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,15 +1,18 @@
 use clap::Parser;
+use corona::{badmail::BadMail, cfg, core::Area, lock::Lock, logging, netmail, store, tosser};
+use log::{error, info, LevelFilter};
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
 
-mod cfg;
 mod cli;
-mod core;
-mod ftn;
-mod store;
-mod tosser;
 
-use self::cli::Args;
+use self::cli::{Args, Bad, Netmail};
 
-fn main() {
+/// Scripts can tell a full disk from other failures
+const EXIT_NO_SPACE: u8 = 3;
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
     let mut cfg_path = dirs::config_dir().unwrap();
@@ -20,17 +23,156 @@ fn main() {
         Ok(cfg) => cfg,
         Err(e) => {
             eprintln!("Cannot open config file {}, reason: {e}", cfg_path.display());
-            return;
+            return ExitCode::FAILURE;
         }
     };
 
-    // TODO: check that there is some space left on drive
+    if let Err(e) = logging::init(&cfg) {
+        eprintln!("Cannot open log file, reason: {e}");
+        return ExitCode::FAILURE;
+    }
 
     match args {
-        Args::Toss => {
-            if let Err(e) = tosser::toss(&cfg) {
-                eprintln!("Toss failed: {e}");
+        Args::Toss { json } => {
+            let _lock = match Lock::for_config(&cfg) {
+                Ok(lock) => lock,
+                Err(e @ corona::Error::Locked(_)) => {
+                    info!("Toss skipped: {e}");
+                    return ExitCode::SUCCESS;
+                }
+                Err(e) => {
+                    error!("Toss failed: {e}");
+                    return exit_code(&e);
+                }
+            };
+
+            let stats = match tosser::toss(&cfg) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    error!("Toss failed: {e}");
+                    return exit_code(&e);
+                }
+            };
+
+            if stats.packets + stats.bundles > 0 && cfg.console_level() >= LevelFilter::Info {
+                println!("{stats}");
+            }
+
+            if let Some(path) = json {
+                if let Err(e) = std::fs::write(&path, stats.to_json() + "\n") {
+                    eprintln!("Cannot write statistics to {}, reason: {e}", path.display());
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        Args::Watch => {
+            if let Err(e) = corona::watch::watch(&cfg_path) {
+                error!("Watch failed: {e}");
+                return exit_code(&e);
+            }
+        }
+        Args::Raw { area, id } => {
+            let msgbase = match cfg.msgbase_path() {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("Failed to read message #{id}, reason: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let name = match Area::Echomail(area.clone()).base_name() {
+                Some(name) => name,
+                None if area == tosser::QUARANTINE => area.clone(),
+                None => {
+                    eprintln!("{area} is not a valid echo tag");
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let path = msgbase.join(name);
+
+            if !path.exists() {
+                eprintln!("There is no area {area}");
+                return ExitCode::FAILURE;
+            }
+
+            match store::MessageBase::open(&path).and_then(|mb| mb.raw_text(id)) {
+                Ok(Some(text)) => match std::io::stdout().write_all(&text) {
+                    // the reader, e.g. `head`, has seen enough
+                    Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                        eprintln!("Cannot write the raw text, reason: {e}");
+                        return ExitCode::FAILURE;
+                    }
+                    _ => {}
+                },
+                Ok(None) => eprintln!("There is no raw text for message #{id} in {area}"),
+                Err(e) => {
+                    eprintln!("Failed to read message #{id}, reason: {e}");
+                    return ExitCode::FAILURE;
+                }
             }
         }
+        Args::Bad(Bad::List) => {
+            let Some(path) = cfg.bad_mail_path() else {
+                eprintln!("Bad mail path is not set");
+                return ExitCode::FAILURE;
+            };
+
+            match BadMail::new(&path).list() {
+                Ok(list) => {
+                    for (file, r) in list {
+                        let name = file.file_name().unwrap_or_default().to_string_lossy();
+                        let origin = Path::new(&r.inbound).join(&r.file);
+                        println!(
+                            "{name}\t{}\t{}\t{} (corona {})",
+                            r.time,
+                            origin.display(),
+                            r.error,
+                            r.version
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to list bad mail, reason: {e}");
+                    return exit_code(&e);
+                }
+            }
+        }
+        Args::Bad(Bad::Retoss { file, .. }) => {
+            let _lock = match Lock::for_config(&cfg) {
+                Ok(lock) => lock,
+                Err(e) => {
+                    error!("Retoss skipped: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            match tosser::retoss(&cfg, file.as_deref()) {
+                Ok(0) => {}
+                Ok(n) => println!("{n} file(s) are still bad"),
+                Err(e) => {
+                    error!("Retoss failed: {e}");
+                    return exit_code(&e);
+                }
+            }
+        }
+        Args::Netmail(Netmail::Trace { id }) => match netmail::trace(&cfg, id) {
+            Ok(trace) => println!("{trace}"),
+            Err(e) => {
+                eprintln!("Trace failed: {e}");
+                return exit_code(&e);
+            }
+        },
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn exit_code(e: &corona::Error) -> ExitCode {
+    if e.is_no_space() {
+        ExitCode::from(EXIT_NO_SPACE)
+    } else {
+        ExitCode::FAILURE
     }
 }
@@ -0,0 +1,518 @@
+use chrono::{Duration, Local, SubsecRound, Utc};
+use encoding::{all::IBM866, EncoderTrap, Encoding};
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::cfg::Config;
+use crate::core::{Address, Area, Attributes, ControlLines, DateFormat, Kludge, Message, User, Via};
+use crate::error::{Error, Result};
+use crate::store::{MessageBase, Route};
+
+const TRACKER_NAME: &str = "Corona";
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Delivery {
+    /// Addressed to one of our AKAs
+    Local,
+    /// Goes further
+    Transit,
+    Undeliverable(String),
+}
+
+/// Where a netmail goes from our point of view, `None` if we have no AKAs or the destination is unknown
+pub(crate) fn delivery(config: &Config, msg: &Message) -> Option<Delivery> {
+    let akas = config.akas();
+    let to = &msg.to.addr;
+
+    if akas.is_empty() || (to.zone == 0 && to.net == 0 && to.node == 0) {
+        return None;
+    }
+
+    if akas.iter().any(|a| a.same_4d(to)) {
+        return Some(Delivery::Local);
+    }
+
+    if to.point != 0 && akas.iter().any(|a| a.point == 0 && a.same_3d(to)) {
+        let known = config.node.as_ref().and_then(|n| n.points.as_ref());
+
+        if known.is_some_and(|p| !p.contains(&to.point)) {
+            return Some(Delivery::Undeliverable(format!("there is no point {to}")));
+        }
+
+        return Some(Delivery::Transit);
+    }
+
+    if let Some(routes) = config.routes() {
+        let links = config.links();
+        // points get their netmail through their node
+        let node = Address { point: 0, ..to.clone() };
+
+        if !links.iter().any(|l| l.same_3d(to)) && !routes.iter().any(|r| r.matches(&node)) {
+            let zone_known =
+                akas.iter().chain(&links).any(|a| a.zone == to.zone) || routes.iter().any(|r| r.in_zone(to.zone));
+
+            return Some(Delivery::Undeliverable(if zone_known {
+                format!("there is no route to {node}")
+            } else {
+                format!("there is no route to zone {}", to.zone)
+            }));
+        }
+    }
+
+    Some(Delivery::Transit)
+}
+
+/// Return receipt (RRQ) for netmail delivered to us, audit trail (ARQ) for transit one
+/// or a bounce for one to an unknown point, or to a node or zone we have no route to. Receipts are never answered to avoid loops.
+pub(crate) fn track(config: &Config, msg: &Message) -> Option<Message> {
+    if msg.area != Area::Netmail || msg.flags.contains(Attributes::IS_RETURN_RECEIPT) {
+        return None;
+    }
+
+    let delivery = delivery(config, msg)?;
+
+    let akas = config.akas();
+    let aka = akas.iter().find(|a| a.zone == msg.from.addr.zone).unwrap_or(&akas[0]);
+    let now = Local::now().naive_local().format("%Y-%m-%d %H:%M:%S");
+
+    match delivery {
+        Delivery::Local if msg.flags.contains(Attributes::RETURN_RECEIPT_REQUEST) => Some(reply(
+            aka,
+            msg,
+            "Return receipt",
+            Attributes::IS_RETURN_RECEIPT,
+            format!(
+                "This is a return receipt for your message:\n\n{}\nIt has been received at {aka} on {now}.",
+                quote_header(msg)
+            ),
+        )),
+        Delivery::Transit if msg.flags.contains(Attributes::AUDIT_REQUEST) => Some(reply(
+            aka,
+            msg,
+            "Audit trail",
+            Attributes::IS_RETURN_RECEIPT,
+            format!(
+                "This is an audit trail for your message:\n\n{}\nIt has passed through {aka} on {now}.",
+                quote_header(msg)
+            ),
+        )),
+        Delivery::Undeliverable(reason) => Some(reply(
+            aka,
+            msg,
+            &format!("Undeliverable: {}", msg.subj),
+            Attributes::default(),
+            format!(
+                "Your message could not be delivered:\n\n{}\nReason: {reason}.",
+                quote_header(msg)
+            ),
+        )),
+        _ => None,
+    }
+}
+
+/// Adds our hop to netmail which is stored here, see FTS-4009
+pub(crate) fn stamp(config: &Config, msg: &mut Message) {
+    if msg.area != Area::Netmail {
+        return;
+    }
+
+    let akas = config.akas();
+
+    if let Some(aka) = akas.iter().find(|a| a.zone == msg.to.addr.zone).or(akas.first()) {
+        let via = via(aka);
+        msg.kludges
+            .lines
+            .get_or_insert(Vec::new())
+            .push(Kludge::new("Via", &via.to_string()));
+        msg.kludges.via.get_or_insert(Vec::new()).push(via);
+    }
+}
+
+fn via(aka: &Address) -> Via {
+    Via {
+        addr: aka.clone(),
+        at: Some(Utc::now().naive_utc().trunc_subsecs(0)),
+        utc: true,
+        software: format!("corona {}", env!("CARGO_PKG_VERSION")),
+    }
+}
+
+fn quote_header(msg: &Message) -> String {
+    format!(
+        "   From: {}, {}\n     To: {}, {}\n   Subj: {}\n   Date: {}\n",
+        msg.from.name, msg.from.addr, msg.to.name, msg.to.addr, msg.subj, msg.posted
+    )
+}
+
+/// Serials are based on the current time and never repeat within a run
+fn new_serial() -> u32 {
+    static LAST: AtomicU32 = AtomicU32::new(0);
+
+    let now = Utc::now().timestamp() as u32;
+    let mut last = LAST.load(Ordering::SeqCst);
+
+    loop {
+        let next = now.max(last.wrapping_add(1));
+
+        match LAST.compare_exchange(last, next, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return next,
+            Err(v) => last = v,
+        }
+    }
+}
+
+fn reply(from: &Address, orig: &Message, subj: &str, flags: Attributes, body: String) -> Message {
+    let now = Local::now();
+    let to = &orig.from.addr;
+    let serial = new_serial();
+    let pid = format!("{} {}", TRACKER_NAME, env!("CARGO_PKG_VERSION"));
+    let tzutc = now.format("%z").to_string().trim_start_matches('+').to_string();
+
+    let mut lines = vec![Kludge::new(
+        "INTL",
+        &format!(
+            "{}:{}/{} {}:{}/{}",
+            to.zone, to.net, to.node, from.zone, from.net, from.node
+        ),
+    )];
+
+    if from.point != 0 {
+        lines.push(Kludge::new("FMPT", &from.point.to_string()));
+    }
+
+    if to.point != 0 {
+        lines.push(Kludge::new("TOPT", &to.point.to_string()));
+    }
+
+    lines.push(Kludge::new("MSGID", &format!("{from} {serial:08x}")));
+
+    if orig.msgid_serial != 0 {
+        lines.push(Kludge::new("REPLY", &format!("{to} {:08x}", orig.msgid_serial)));
+    }
+
+    lines.push(Kludge::new("PID", &pid));
+    lines.push(Kludge::new("TZUTC", &tzutc));
+
+    let mut raw = String::new();
+
+    for kl in &lines {
+        let sep = if matches!(kl.name.as_str(), "INTL" | "FMPT" | "TOPT") {
+            " "
+        } else {
+            ": "
+        };
+        raw.push_str(&format!("\u{1}{}{}{}\r", kl.name, sep, kl.value));
+    }
+
+    raw.push_str(&body.replace('\n', "\r"));
+    raw.push('\r');
+
+    // Via lines follow the text
+    let via = via(from);
+    raw.push_str(&format!("\u{1}Via {via}\r"));
+    lines.push(Kludge::new("Via", &via.to_string()));
+
+    let mut kludges = ControlLines::empty();
+    kludges.pid = Some(pid);
+    kludges.tzutc = Some(tzutc);
+    kludges.lines = Some(lines);
+    kludges.via = Some(vec![via]);
+
+    Message {
+        area: Area::Netmail,
+        posted: now.naive_local(),
+        posted_format: Some(DateFormat::Fts0001),
+        from: User {
+            addr: from.clone(),
+            name: TRACKER_NAME.to_string(),
+            ext_addr: None,
+        },
+        to: User {
+            addr: to.clone(),
+            name: orig.from.name.clone(),
+            ext_addr: None,
+        },
+        flags: Attributes::PRIVATE | Attributes::LOCAL | Attributes::KILL_SENT | flags,
+        msgid_serial: serial,
+        reply_serial: Some(orig.msgid_serial).filter(|x| *x != 0),
+        msgid_addr: None,
+        reply_addr: None,
+        subj: subj.to_string(),
+        body,
+        tear_line: String::new(),
+        origin: String::new(),
+        kludges,
+        raw: IBM866.encode(&raw, EncoderTrap::Replace).unwrap_or_default(),
+    }
+}
+
+/// Route a netmail took according to its Via lines, printed as a table
+pub struct Trace {
+    pub id: i64,
+    pub(crate) route: Route,
+}
+
+pub fn trace(config: &Config, id: i64) -> Result<Trace> {
+    let mb = MessageBase::open(&config.msgbase_path()?.join("netmail"))?;
+
+    match mb.route(id) {
+        Ok(route) => Ok(Trace { id, route }),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NotFound(format!("netmail #{id}"))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (id, route) = (self.id, &self.route);
+        let hops = &route.hops;
+
+        match route.utc_offset {
+            Some(offset) => writeln!(f, "Netmail #{id} posted {} {offset}", route.posted)?,
+            None => writeln!(f, "Netmail #{id} posted {} (unknown zone)", route.posted)?,
+        }
+        write!(f, "Attributes: {}", route.flags)?;
+
+        if hops.is_empty() {
+            return write!(f, "\nThere are no Via lines");
+        }
+
+        writeln!(f)?;
+        writeln!(f)?;
+        write!(
+            f,
+            "{:>3}  {:<24} {:<23} {:>14}  Software",
+            "#", "Address", "Time", "Delay"
+        )?;
+
+        let mut prev = None;
+
+        for (i, hop) in hops.iter().enumerate() {
+            let at = hop.at.map_or(String::new(), |x| {
+                format!("{}{}", x.format("%Y-%m-%d %H:%M:%S"), if hop.utc { " UTC" } else { "" })
+            });
+
+            let delay = match (prev, hop.at) {
+                (Some(p), Some(a)) => format_duration(a - p),
+                _ => String::new(),
+            };
+
+            write!(
+                f,
+                "\n{:>3}  {:<24} {:<23} {:>14}  {}",
+                i,
+                hop.addr.to_string(),
+                at,
+                delay,
+                hop.software
+            )?;
+
+            if hop.at.is_some() {
+                prev = hop.at;
+            }
+        }
+
+        let mut stamps = hops.iter().filter_map(|x| x.at);
+
+        if let (Some(first), Some(last)) = (stamps.next(), stamps.next_back()) {
+            writeln!(f)?;
+            writeln!(f)?;
+            write!(f, "Total transit time {}", format_duration(last - first))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let sign = if d < Duration::zero() { "-" } else { "+" };
+    let secs = d.num_seconds().abs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    if days > 0 {
+        format!(
+            "{sign}{days}d {:02}:{:02}:{:02}",
+            secs / 3600,
+            secs % 3600 / 60,
+            secs % 60
+        )
+    } else {
+        format!("{sign}{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{delivery, stamp, trace, track, Delivery};
+    use crate::cfg::Config;
+    use crate::core::{Address, Area, Attributes, ControlLines, DateFormat, Message, User};
+    use crate::error::Error;
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            [node]
+            address = ["2:5020/1", "1:123/4"]
+            points = [5, 7]
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn netmail(to: Address, flags: Attributes) -> Message {
+        Message {
+            area: Area::Netmail,
+            posted: chrono::NaiveDate::from_ymd(2020, 2, 28).and_hms(14, 0, 18),
+            posted_format: Some(DateFormat::Fts0001),
+            from: User {
+                addr: Address::new_4d(2, 5030, 100, 0),
+                name: "John Doe".to_string(),
+                ext_addr: None,
+            },
+            to: User {
+                addr: to,
+                name: "Sysop".to_string(),
+                ext_addr: None,
+            },
+            flags,
+            msgid_serial: 0x12345678,
+            reply_serial: None,
+            msgid_addr: None,
+            reply_addr: None,
+            subj: "Ping".to_string(),
+            body: "Hello".to_string(),
+            tear_line: String::new(),
+            origin: String::new(),
+            kludges: ControlLines::empty(),
+            raw: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn netmail_delivery() {
+        let cfg = config();
+        let none = Attributes::default();
+
+        assert_eq!(
+            delivery(&cfg, &netmail(Address::new_4d(2, 5020, 1, 0), none)),
+            Some(Delivery::Local)
+        );
+        assert_eq!(
+            delivery(&cfg, &netmail(Address::new_4d(2, 5020, 1, 7), none)),
+            Some(Delivery::Transit)
+        );
+        assert_eq!(
+            delivery(&cfg, &netmail(Address::new_4d(2, 5020, 2, 0), none)),
+            Some(Delivery::Transit)
+        );
+        assert_eq!(
+            delivery(&cfg, &netmail(Address::new_4d(2, 5020, 1, 8), none)),
+            Some(Delivery::Undeliverable("there is no point 2:5020/1.8".to_string()))
+        );
+        assert_eq!(delivery(&cfg, &netmail(Address::empty(), none)), None);
+    }
+
+    #[test]
+    fn bounce_without_route() {
+        let cfg: Config = r#"
+            [node]
+            address = ["2:5020/1"]
+            routes = ["2:5020/*"]
+
+            [[link]]
+            address = "2:5030/100"
+            "#
+        .parse()
+        .unwrap();
+        let none = Attributes::default();
+
+        let delivery = |to: &str| delivery(&cfg, &netmail(to.parse().unwrap(), none));
+
+        assert_eq!(delivery("2:5020/2.1"), Some(Delivery::Transit));
+        assert_eq!(delivery("2:5030/100"), Some(Delivery::Transit));
+        assert_eq!(delivery("2:5030/100.3"), Some(Delivery::Transit));
+        assert_eq!(
+            delivery("2:5040/1.2"),
+            Some(Delivery::Undeliverable("there is no route to 2:5040/1".to_string()))
+        );
+        assert_eq!(
+            delivery("3:640/1"),
+            Some(Delivery::Undeliverable("there is no route to zone 3".to_string()))
+        );
+
+        let bounce = track(&cfg, &netmail("3:640/1".parse().unwrap(), none)).unwrap();
+        assert!(bounce.body.ends_with("Reason: there is no route to zone 3."));
+        assert_eq!(bounce.to.addr, Address::new_4d(2, 5030, 100, 0));
+    }
+
+    #[test]
+    fn return_receipt() {
+        let msg = netmail(Address::new_4d(2, 5020, 1, 0), Attributes::RETURN_RECEIPT_REQUEST);
+        let rcpt = track(&config(), &msg).unwrap();
+
+        assert_eq!(rcpt.subj, "Return receipt");
+        assert_eq!(rcpt.from.addr, Address::new_4d(2, 5020, 1, 0));
+        assert_eq!(rcpt.to.addr, msg.from.addr);
+        assert_eq!(rcpt.to.name, "John Doe");
+        assert_eq!(rcpt.reply_serial, Some(0x12345678));
+        assert!(rcpt.flags.contains(Attributes::IS_RETURN_RECEIPT | Attributes::PRIVATE));
+
+        // receipts are not answered
+        assert!(track(&config(), &rcpt).is_none());
+    }
+
+    #[test]
+    fn audit_trail_and_bounce() {
+        let cfg = config();
+
+        let msg = netmail(Address::new_4d(2, 5020, 2, 0), Attributes::AUDIT_REQUEST);
+        assert_eq!(track(&cfg, &msg).unwrap().subj, "Audit trail");
+
+        let msg = netmail(Address::new_4d(2, 5020, 2, 0), Attributes::RETURN_RECEIPT_REQUEST);
+        assert!(track(&cfg, &msg).is_none());
+
+        let msg = netmail(Address::new_4d(2, 5020, 1, 9), Attributes::default());
+        let bounce = track(&cfg, &msg).unwrap();
+        assert_eq!(bounce.subj, "Undeliverable: Ping");
+        assert!(bounce.body.ends_with("Reason: there is no point 2:5020/1.9."));
+    }
+
+    #[test]
+    fn stamp_via() {
+        let cfg = config();
+
+        let mut msg = netmail(Address::new_4d(1, 123, 4, 0), Attributes::default());
+        stamp(&cfg, &mut msg);
+
+        let via = msg.kludges.via.as_ref().unwrap();
+        assert_eq!(via.len(), 1);
+        assert_eq!(via[0].addr, Address::new_4d(1, 123, 4, 0));
+        assert!(via[0].utc && via[0].at.is_some());
+        assert_eq!(via[0].software, format!("corona {}", env!("CARGO_PKG_VERSION")));
+
+        let line = msg.kludges.lines.as_ref().unwrap().last().unwrap();
+        assert_eq!((line.name.as_str(), line.value.clone()), ("Via", via[0].to_string()));
+
+        // transit netmail gets our hop in its zone
+        let mut msg = netmail(Address::new_4d(2, 5020, 2, 0), Attributes::default());
+        assert_eq!(delivery(&cfg, &msg), Some(Delivery::Transit));
+        stamp(&cfg, &mut msg);
+        assert_eq!(msg.kludges.via.unwrap()[0].addr, Address::new_4d(2, 5020, 1, 0));
+
+        // replies carry our hop as well
+        let msg = netmail(Address::new_4d(2, 5020, 1, 0), Attributes::RETURN_RECEIPT_REQUEST);
+        let rcpt = track(&cfg, &msg).unwrap();
+        assert_eq!(rcpt.kludges.via.unwrap()[0].addr, Address::new_4d(2, 5020, 1, 0));
+        assert!(String::from_utf8_lossy(&rcpt.raw).contains("\u{1}Via 2:5020/1 @"));
+
+        let mut echo = netmail(Address::new_4d(2, 5020, 1, 0), Attributes::default());
+        echo.area = Area::Echomail("TEST.ECHO".to_string());
+        stamp(&cfg, &mut echo);
+        assert!(echo.kludges.via.is_none());
+    }
+
+    #[test]
+    fn trace_without_msgbase() {
+        assert!(matches!(trace(&config(), 1), Err(Error::Config(_))));
+    }
+}
@@ -0,0 +1,188 @@
+use rusqlite::{named_params, Connection, OptionalExtension, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Inbound files being tossed. Each gets an id, never reused, which message bases keep
+/// with the last position stored from the file, see `MessageBase::toss_from`. The journal
+/// records those bases, so the positions can be dropped once the file is gone.
+pub(crate) struct Journal {
+    conn: Connection,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+
+        conn.execute_batch(
+            r#"
+            create table if not exists inbound (
+                id              integer primary key autoincrement,
+                name            text not null unique,
+                size            integer not null,
+                modified        integer not null
+            );
+
+            create table if not exists positions (
+                inbound_id      integer not null,
+                base            text not null,
+                primary key (inbound_id, base)
+            );
+            "#,
+        )?;
+
+        Ok(Self { conn })
+    }
+
+    /// Id of the inbound file `name` and whether an earlier toss of it has been interrupted.
+    /// A file of another size or modification time is a new one with the same name.
+    pub fn start(&self, name: &str, size: u64, modified: SystemTime) -> Result<(i64, bool)> {
+        let modified = modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as i64);
+
+        let found = self
+            .conn
+            .query_row(
+                "select id, size = :size and modified = :modified from inbound where name = :name",
+                named_params! {
+                    ":name": name,
+                    ":size": size,
+                    ":modified": modified,
+                },
+                |r| Ok((r.get::<_, i64>(0)?, r.get::<_, bool>(1)?)),
+            )
+            .optional()?;
+
+        match found {
+            Some((id, true)) => return Ok((id, true)),
+            Some((id, false)) => self.finish(id)?,
+            None => {}
+        }
+
+        self.conn.execute(
+            "insert into inbound (name, size, modified) values (:name, :size, :modified)",
+            named_params! {
+                ":name": name,
+                ":size": size,
+                ":modified": modified,
+            },
+        )?;
+
+        Ok((self.conn.last_insert_rowid(), false))
+    }
+
+    /// The file is gone from inbound, either tossed or moved to bad mail
+    pub fn finish(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("delete from inbound where id = :id", named_params! { ":id": id })?;
+
+        Ok(())
+    }
+
+    /// Records that the message base at `base` keeps a position of the file `id`
+    pub fn stored_in(&self, id: i64, base: &Path) -> Result<()> {
+        self.conn.execute(
+            "insert or ignore into positions (inbound_id, base) values (:id, :base)",
+            named_params! {
+                ":id": id,
+                ":base": base.to_string_lossy(),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Message bases which keep positions of files that are finished
+    pub fn stale_positions(&self) -> Result<Vec<(i64, PathBuf)>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            select
+                inbound_id, base
+            from
+                positions
+            where
+                inbound_id not in (select id from inbound)
+            "#,
+        )?;
+
+        let stale = stmt
+            .query_map([], |r| Ok((r.get(0)?, PathBuf::from(r.get::<_, String>(1)?))))?
+            .collect();
+
+        stale
+    }
+
+    /// The position of the file `id` has been dropped from the message base at `base`
+    pub fn forgotten(&self, id: i64, base: &Path) -> Result<()> {
+        self.conn.execute(
+            "delete from positions where inbound_id = :id and base = :base",
+            named_params! {
+                ":id": id,
+                ":base": base.to_string_lossy(),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Forgets files which are no longer in inbound
+    pub fn retain(&self, names: &[&str]) -> Result<()> {
+        let mut stmt = self.conn.prepare("select id, name from inbound")?;
+        let gone = stmt
+            .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?
+            .filter(|r| r.as_ref().map_or(true, |(_, name)| !names.contains(&name.as_str())))
+            .map(|r| r.map(|(id, _)| id))
+            .collect::<Result<Vec<_>>>()?;
+
+        for id in gone {
+            self.finish(id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Journal;
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn resume_only_the_same_file() {
+        let journal = Journal::open(Path::new(":memory:")).unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        let (id, resumed) = journal.start("00000001.pkt", 100, modified).unwrap();
+        assert!(!resumed);
+        assert_eq!(journal.start("00000001.pkt", 100, modified).unwrap(), (id, true));
+
+        // same name, another file
+        let (new_id, resumed) = journal.start("00000001.pkt", 200, modified).unwrap();
+        assert!(!resumed);
+        assert!(new_id > id);
+
+        journal.finish(new_id).unwrap();
+        let (id, resumed) = journal.start("00000001.pkt", 200, modified).unwrap();
+        assert!(!resumed);
+        assert!(id > new_id);
+
+        let (other, _) = journal.start("00000002.pkt", 100, modified).unwrap();
+        journal.retain(&["00000002.pkt"]).unwrap();
+        assert_eq!(journal.start("00000002.pkt", 100, modified).unwrap(), (other, true));
+        assert!(!journal.start("00000001.pkt", 200, modified).unwrap().1);
+
+        // positions outlive the file until they are dropped
+        let (id, _) = journal.start("00000001.pkt", 200, modified).unwrap();
+        journal.stored_in(id, Path::new("/msgbase/area")).unwrap();
+        journal.stored_in(id, Path::new("/msgbase/area")).unwrap();
+        assert!(journal.stale_positions().unwrap().is_empty());
+
+        journal.finish(id).unwrap();
+        assert_eq!(
+            journal.stale_positions().unwrap(),
+            [(id, Path::new("/msgbase/area").to_path_buf())]
+        );
+
+        journal.forgotten(id, Path::new("/msgbase/area")).unwrap();
+        assert!(journal.stale_positions().unwrap().is_empty());
+    }
+}
@@ -1,13 +1,54 @@
-use rusqlite::{named_params, Connection, Error, Result, Transaction};
-use std::{cell::RefCell, fmt::Write, path::Path};
+use chrono::{Duration, FixedOffset, NaiveDateTime};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use log::debug;
+use rusqlite::{named_params, types::Type, Connection, Error, OptionalExtension, Result, Transaction};
+use std::{cell::RefCell, fmt::Write, io::Read, path::Path};
 
-use crate::core::{Message, NetNodePair};
+use crate::core::{parse_tzutc, Address, Area, Attributes, Kludge, Message, NetNodePair, Via};
 
 #[macro_use]
 mod sql_macro;
+mod journal;
 
+pub(crate) use journal::Journal;
+
+pub(crate) struct Route {
+    pub posted: NaiveDateTime,
+    /// `None` if the zone of `posted` is unknown
+    pub utc_offset: Option<FixedOffset>,
+    pub flags: Attributes,
+    pub hops: Vec<Via>,
+}
+
+/// Where a message was read from: packet `offset` in bundle entry `member` (0 for a bare packet)
+/// of the inbound file with `Journal` id `inbound`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Position {
+    pub inbound: i64,
+    pub member: usize,
+    pub offset: u64,
+}
+
+/// SQLite database of one area, created on first open and migrated to the current schema
+///
+/// ```
+/// use corona::{core, ftn::Package, store::MessageBase};
+/// use std::{fs::File, path::Path};
+///
+/// let mut pkg = Package::read(File::open("tests/corpus/lf_newlines.pkt")?)?;
+/// let m = pkg.next().unwrap()?;
+/// let msg = core::message_from(&pkg.header, m, |_| true)?;
+///
+/// let mut base = MessageBase::open(Path::new(":memory:"))?;
+/// base.keep_raw(true);
+///
+/// let id = base.toss(msg)?;
+/// assert!(base.raw_text(id)?.is_some());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
 pub struct MessageBase {
     conn: RefCell<Connection>,
+    keep_raw: bool,
 }
 
 impl MessageBase {
@@ -20,13 +61,190 @@ impl MessageBase {
 
         Ok(Self {
             conn: RefCell::new(conn),
+            keep_raw: false,
+        })
+    }
+
+    /// Store the original message text in `raw_messages` for every tossed message which has it.
+    /// Messages of areas which don't keep raw text come without it, see [`crate::core::message_from`].
+    pub fn keep_raw(&mut self, keep: bool) {
+        self.keep_raw = keep;
+    }
+
+    /// Original message text, if it has been stored at toss time
+    pub fn raw_text(&self, id: i64) -> Result<Option<Vec<u8>>> {
+        let data = self
+            .conn
+            .borrow()
+            .query_row(
+                "select text from raw_messages where message_id = :id",
+                named_params! { ":id": id },
+                |r| r.get::<_, Vec<u8>>(0),
+            )
+            .optional()?;
+
+        data.map(|x| decompress(&x)).transpose()
+    }
+
+    /// Posted date and attributes of a message and the hops it went through, in order
+    pub(crate) fn route(&self, id: i64) -> Result<Route> {
+        let conn = self.conn.borrow();
+
+        let (posted, utc_offset, flags) = conn.query_row(
+            "select posted, utc_offset, flags from messages where id = :id",
+            named_params! { ":id": id },
+            |r| {
+                Ok((
+                    r.get::<_, NaiveDateTime>(0)?,
+                    r.get::<_, Option<i32>>(1)?,
+                    r.get::<_, u32>(2)?,
+                ))
+            },
+        )?;
+
+        let hops = conn
+            .prepare(
+                r#"
+                select
+                    v.zone,
+                    v.net,
+                    v.node,
+                    v.point,
+                    v.domain,
+                    v.at,
+                    v.utc,
+                    coalesce(s.name, '')
+                from
+                    vias v
+                    left join software s on s.id = v.software_id
+                where
+                    v.message_id = :id
+                order by
+                    v.position
+                "#,
+            )?
+            .query_map(named_params! { ":id": id }, |r| {
+                Ok(Via {
+                    addr: Address::full(r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?),
+                    at: r.get(5)?,
+                    utc: r.get(6)?,
+                    software: r.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Route {
+            posted,
+            utc_offset: utc_offset.and_then(|m| FixedOffset::east_opt(m * 60)),
+            flags: Attributes::from_bits(flags),
+            hops,
         })
     }
 
+    /// Stores `msg` and returns its id, or -1 if it is a duplicate
     pub fn toss(&self, msg: Message) -> Result<i64> {
         let mut conn = self.conn.borrow_mut();
         let tran = conn.transaction()?;
 
+        let id = self.insert(&tran, msg)?;
+
+        tran.commit()?;
+
+        Ok(id)
+    }
+
+    /// Stores `msg`, the `reply` to it unless `msg` is a duplicate, and `pos` in one transaction,
+    /// so that `tossed` tells exactly whether they are stored
+    pub(crate) fn toss_from(&self, pos: &Position, msg: Message, reply: Option<Message>) -> Result<i64> {
+        let mut conn = self.conn.borrow_mut();
+        let tran = conn.transaction()?;
+
+        let id = self.insert(&tran, msg)?;
+
+        if let (true, Some(reply)) = (id > 0, reply) {
+            self.insert(&tran, reply)?;
+        }
+
+        set_position(&tran, pos)?;
+        tran.commit()?;
+
+        Ok(id)
+    }
+
+    /// Whether the message at `pos` or a later one of the same inbound file has been stored
+    pub(crate) fn tossed(&self, pos: &Position) -> Result<bool> {
+        self.conn
+            .borrow()
+            .query_row(
+                r#"
+                select
+                    (member, offset) >= (:member, :offset)
+                from
+                    toss_positions
+                where
+                    inbound_id = :inbound
+                "#,
+                named_params! {
+                    ":inbound": pos.inbound,
+                    ":member": pos.member,
+                    ":offset": pos.offset,
+                },
+                |r| r.get(0),
+            )
+            .optional()
+            .map(|x| x.unwrap_or(false))
+    }
+
+    /// Drops the position of an inbound file which has been tossed completely
+    pub(crate) fn forget(&self, inbound: i64) -> Result<()> {
+        self.conn.borrow().execute(
+            "delete from toss_positions where inbound_id = :inbound",
+            named_params! { ":inbound": inbound },
+        )?;
+
+        Ok(())
+    }
+
+    /// Tosses a message which doesn't belong here, `link` is the node the packet came from
+    pub(crate) fn quarantine(&self, pos: &Position, msg: Message, link: &Address, reason: &str) -> Result<i64> {
+        let area = match msg.area {
+            Area::Netmail => "netmail".to_string(),
+            Area::Echomail(ref name) => name.clone(),
+        };
+
+        let mut conn = self.conn.borrow_mut();
+        let tran = conn.transaction()?;
+
+        let id = self.insert(&tran, msg)?;
+
+        if id > 0 {
+            tran.execute(
+                "insert into quarantined (message_id, area, link, reason) values (:id, :area, :link, :reason)",
+                named_params! {
+                    ":id": id,
+                    ":area": area,
+                    ":link": link.to_string(),
+                    ":reason": reason,
+                },
+            )?;
+
+            tran.execute(
+                r#"
+                insert into quarantine_links (link, count, last) values (:link, 1, current_timestamp)
+                on conflict (link) do update set count = count + 1, last = current_timestamp
+                "#,
+                named_params! { ":link": link.to_string() },
+            )?;
+        }
+
+        set_position(&tran, pos)?;
+        tran.commit()?;
+
+        Ok(id)
+    }
+
+    /// Stores `msg` unless it is a dupe, which gives -1
+    fn insert(&self, tran: &Transaction, msg: Message) -> Result<i64> {
         if let Ok(id) = tran.query_row(
             "
             select
@@ -43,7 +261,7 @@ impl MessageBase {
             },
             |r| r.get::<_, i64>(0),
         ) {
-            eprintln!(
+            debug!(
                 "Message ({}, {:08x}) skipped because of a duplicate #{}",
                 msg.posted, msg.msgid_serial, id
             );
@@ -51,8 +269,10 @@ impl MessageBase {
             return Ok(-1); // TODO: report that dupe has been skipped
         }
 
-        let subj = get_subj_id(&tran, &msg.subj)?;
-        let from = get_user_id(&tran, &msg.from)?;
+        let (utc_offset, posted_utc) = (msg.utc_offset(), msg.posted_utc());
+
+        let subj = get_subj_id(tran, &msg.subj)?;
+        let from = get_user_id(tran, &msg.from)?;
 
         let to = {
             let id = if let Some(id) = msg.reply_serial {
@@ -85,22 +305,25 @@ impl MessageBase {
             if id > 0 {
                 id
             } else {
-                get_user_id(&tran, &msg.to)?
+                get_user_id(tran, &msg.to)?
             }
         };
 
-        let seen_by = get_seenby_id(&tran, &msg.kludges.seen_by)?;
-        let path = get_path_id(&tran, &msg.kludges.path)?;
-        let pid = msg.kludges.pid.map(|x| get_software_id(&tran, &x)).transpose()?;
-        let tid = msg.kludges.tid.map(|x| get_software_id(&tran, &x)).transpose()?;
-        let tear_line = get_tear_line_id(&tran, &msg.tear_line)?;
-        let origin = get_origin_id(&tran, &msg.origin)?;
+        let seen_by = get_seenby_id(tran, &msg.kludges.seen_by)?;
+        let path = get_path_id(tran, &msg.kludges.path)?;
+        let pid = msg.kludges.pid.map(|x| get_software_id(tran, &x)).transpose()?;
+        let tid = msg.kludges.tid.map(|x| get_software_id(tran, &x)).transpose()?;
+        let tear_line = get_tear_line_id(tran, &msg.tear_line)?;
+        let origin = get_origin_id(tran, &msg.origin)?;
 
         tran.execute(
             r#"
             insert into messages (
                 posted,
+                posted_format,
                 tzutc,
+                utc_offset,
+                posted_utc,
                 msgid_serial,
                 reply_serial,
                 msgid_address,
@@ -118,7 +341,10 @@ impl MessageBase {
                 path_id
             ) values (
                 replace(:posted, 'T', ' '),
+                :posted_format,
                 nullif(trim(:tzutc), ''),
+                :utc_offset,
+                replace(:posted_utc, 'T', ' '),
                 :msgid_serial,
                 nullif(:reply_serial, 0),
                 nullif(trim(:msgid_addr), ''),
@@ -137,14 +363,17 @@ impl MessageBase {
             )"#,
             named_params! {
                 ":posted": msg.posted,
+                ":posted_format": msg.posted_format.map(|f| f.as_str()),
                 ":tzutc": msg.kludges.tzutc,
+                ":utc_offset": utc_offset.map(offset_minutes),
+                ":posted_utc": posted_utc,
                 ":msgid_serial": msg.msgid_serial,
                 ":reply_serial": msg.reply_serial,
                 ":msgid_addr": msg.msgid_addr,
                 ":reply_addr": msg.reply_addr,
                 ":from": from,
                 ":to": to,
-                ":flags": msg.flags,
+                ":flags": msg.flags.bits(),
                 ":subj": subj,
                 ":body": msg.body,
                 ":tear_line": tear_line,
@@ -158,24 +387,113 @@ impl MessageBase {
 
         let id = tran.last_insert_rowid();
 
-        if let Some(ref kludges) = msg.kludges.custom {
-            for kl in kludges {
+        if let Some(ref kludges) = msg.kludges.lines {
+            for (pos, kl) in kludges.iter().enumerate() {
                 tran.execute(
-                    "insert into kludges (message_id, kludge) values (:id, :kludge)",
+                    "insert into kludges (message_id, position, name, value) values (:id, :pos, :name, :value)",
                     named_params! {
                         ":id": id,
-                        ":kludge": kl,
+                        ":pos": pos,
+                        ":name": kl.name,
+                        ":value": kl.value,
                     },
                 )?;
             }
         }
 
-        tran.commit()?;
+        if let Some(ref via) = msg.kludges.via {
+            for (pos, v) in via.iter().enumerate() {
+                let software = get_software_id(tran, &v.software)?;
+
+                tran.execute(
+                    r#"
+                    insert into vias (
+                        message_id,
+                        position,
+                        zone,
+                        net,
+                        node,
+                        point,
+                        domain,
+                        at,
+                        utc,
+                        software_id
+                    ) values (
+                        :id,
+                        :pos,
+                        :zone,
+                        :net,
+                        :node,
+                        :point,
+                        nullif(lower(trim(:domain)), ''),
+                        :at,
+                        :utc,
+                        nullif(:software, 0)
+                    )"#,
+                    named_params! {
+                        ":id": id,
+                        ":pos": pos,
+                        ":zone": v.addr.zone,
+                        ":net": v.addr.net,
+                        ":node": v.addr.node,
+                        ":point": v.addr.point,
+                        ":domain": v.addr.domain,
+                        ":at": v.at,
+                        ":utc": v.utc,
+                        ":software": software,
+                    },
+                )?;
+            }
+        }
+
+        if self.keep_raw && !msg.raw.is_empty() {
+            tran.execute(
+                "insert into raw_messages (message_id, text) values (:id, :text)",
+                named_params! {
+                    ":id": id,
+                    ":text": compress(&msg.raw)?,
+                },
+            )?;
+        }
 
         Ok(id)
     }
 }
 
+fn set_position(tran: &Transaction, pos: &Position) -> Result<()> {
+    tran.execute(
+        r#"
+        insert or replace into toss_positions (inbound_id, member, offset) values (:inbound, :member, :offset)
+        "#,
+        named_params! {
+            ":inbound": pos.inbound,
+            ":member": pos.member,
+            ":offset": pos.offset,
+        },
+    )?;
+
+    Ok(())
+}
+
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+    enc.write_all(data)
+        .and_then(|_| enc.finish())
+        .map_err(|e| Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut ret = Vec::new();
+
+    ZlibDecoder::new(data)
+        .read_to_end(&mut ret)
+        .map_err(|e| Error::FromSqlConversionFailure(0, Type::Blob, Box::new(e)))?;
+
+    Ok(ret)
+}
+
 fn get_user_id(tran: &Transaction, user: &crate::core::User) -> Result<i64> {
     select_or_insert!(
         tran,
@@ -190,8 +508,8 @@ fn get_user_id(tran: &Transaction, user: &crate::core::User) -> Result<i64> {
             and coalesce(net, 0) = :net
             and coalesce(node, 0) = :node
             and coalesce(point, 0) = :point
-            and coalesce(domain, '') = trim(:domain)
-            and coalesce(foreign_address, '') = trim(:ext_addr)
+            and lower(coalesce(domain, '')) = lower(coalesce(trim(:domain), ''))
+            and coalesce(foreign_address, '') = coalesce(trim(:ext_addr), '')
         "#,
         r#"
         insert into users (
@@ -208,7 +526,7 @@ fn get_user_id(tran: &Transaction, user: &crate::core::User) -> Result<i64> {
             nullif(:net, 0),
             nullif(:node, 0),
             nullif(:point, 0),
-            nullif(trim(:domain), ''),
+            nullif(lower(trim(:domain)), ''),
             nullif(trim(:ext_addr), '')
         )"#,
         named_params! {
@@ -424,7 +742,9 @@ fn get_path_id(tran: &Transaction, path: &Option<Vec<NetNodePair>>) -> Result<i6
     )
 }
 
-fn prepare_database(conn: Connection) -> Result<Connection> {
+fn prepare_database(mut conn: Connection) -> Result<Connection> {
+    migrate_kludges(&mut conn)?;
+
     conn.execute_batch(
         r#"
 PRAGMA page_size = 8192;
@@ -486,11 +806,20 @@ create table if not exists origins (
     origin          text not null
 );
 
+-- attributes (names of message flags)
+create table if not exists attributes (
+    mask            integer primary key,
+    name            text not null
+);
+
 -- messages
 create table if not exists messages (
     id              integer primary key autoincrement,
     posted          text not null,
+    posted_format   text,
     tzutc           text,
+    utc_offset      integer,
+    posted_utc      text,
     tossed          text default (current_timestamp),
     msgid_serial    integer not null,
     reply_serial    integer,
@@ -517,14 +846,202 @@ create index if not exists posted_index on messages (posted);
 -- kludges
 create table if not exists kludges (
     message_id      integer not null references messages (id),
-    kludge          text not null
+    position        integer not null,
+    name            text not null,
+    value           text not null
+);
+
+create unique index if not exists kludge_position_index on kludges (message_id, position);
+create index if not exists kludge_name_index on kludges (name);
+
+-- vias (netmail hops)
+create table if not exists vias (
+    message_id      integer not null references messages (id),
+    position        integer not null,
+    zone            integer not null,
+    net             integer not null,
+    node            integer not null,
+    point           integer not null,
+    domain          text,
+    at              text,
+    utc             integer not null,
+    software_id     integer references software (id)
+);
+
+create unique index if not exists via_index on vias (message_id, position);
+
+-- quarantined (messages which didn't get into their areas)
+create table if not exists quarantined (
+    message_id      integer primary key references messages (id),
+    area            text not null,
+    link            text not null,
+    reason          text not null
+);
+
+-- quarantine_links (number of quarantined messages per link)
+create table if not exists quarantine_links (
+    link            text primary key,
+    count           integer not null,
+    last            text not null
+);
+
+-- toss_positions (last message stored from each inbound file, until the file is tossed completely)
+create table if not exists toss_positions (
+    inbound_id      integer primary key,
+    member          integer not null,
+    offset          integer not null
 );
 
-create index if not exists kludge_index on kludges (message_id);
+-- raw_messages (zlib compressed original text)
+create table if not exists raw_messages (
+    message_id      integer primary key references messages (id),
+    text            blob not null
+);
 
 commit;
     "#,
     )?;
 
+    // columns of older databases are added at once, a failure leaves none of them
+    let tran = conn.transaction()?;
+
+    // how the posted date was written, null if it was taken from the packet header
+    add_column(&tran, "messages", "posted_format", "text")?;
+
+    // minutes east of UTC from TZUTC and posted converted with them, both null if the zone is unknown
+    let offset_added = add_column(&tran, "messages", "utc_offset", "integer")?;
+    let utc_added = add_column(&tran, "messages", "posted_utc", "text")?;
+
+    if offset_added || utc_added {
+        fill_posted_utc(&tran)?;
+    }
+
+    tran.commit()?;
+
+    conn.execute_batch("create index if not exists posted_utc_index on messages (posted_utc)")?;
+
+    // names of message attribute bits, e.g. `select * from messages m, attributes a where m.flags & a.mask`
+    for (a, name) in Attributes::NAMES {
+        conn.execute(
+            "insert or replace into attributes (mask, name) values (:mask, :name)",
+            named_params! {
+                ":mask": a.bits(),
+                ":name": name,
+            },
+        )?;
+    }
+
     Ok(conn)
 }
+
+/// Splits `kludges (message_id, kludge)` rows of an older database into name/value ones
+fn migrate_kludges(conn: &mut Connection) -> Result<()> {
+    let old: i64 = conn.query_row(
+        "select count(*) from pragma_table_info('kludges') where name = 'kludge'",
+        [],
+        |r| r.get(0),
+    )?;
+
+    if old == 0 {
+        return Ok(());
+    }
+
+    let tran = conn.transaction()?;
+
+    let rows = tran
+        .prepare("select message_id, kludge from kludges order by message_id, rowid")?
+        .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>>>()?;
+
+    tran.execute_batch(
+        r#"
+drop index if exists kludge_index;
+drop table kludges;
+
+create table kludges (
+    message_id      integer not null references messages (id),
+    position        integer not null,
+    name            text not null,
+    value           text not null
+);
+        "#,
+    )?;
+
+    let mut pos = 0;
+    let mut prev = None;
+
+    for (id, kludge) in rows {
+        pos = if prev == Some(id) { pos + 1 } else { 0 };
+        prev = Some(id);
+
+        let kl = Kludge::from(kludge.as_str());
+
+        tran.execute(
+            "insert into kludges (message_id, position, name, value) values (:id, :pos, :name, :value)",
+            named_params! {
+                ":id": id,
+                ":pos": pos,
+                ":name": kl.name,
+                ":value": kl.value,
+            },
+        )?;
+    }
+
+    tran.commit()
+}
+
+/// Adds a column missing in a table of an older database, returns whether it was added
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<bool> {
+    let found: i64 = conn.query_row(
+        "select count(*) from pragma_table_info(:table) where name = :column",
+        named_params! {
+            ":table": table,
+            ":column": column,
+        },
+        |r| r.get(0),
+    )?;
+
+    if found == 0 {
+        conn.execute_batch(&format!("alter table {table} add column {column} {decl}"))?;
+    }
+
+    Ok(found == 0)
+}
+
+/// Converts posted dates of messages tossed before TZUTC was parsed
+fn fill_posted_utc(conn: &Connection) -> Result<()> {
+    let rows = conn
+        .prepare(
+            r#"
+            select id, posted, tzutc from messages
+            where tzutc is not null and (utc_offset is null or posted_utc is null)
+            "#,
+        )?
+        .query_map([], |r| {
+            Ok((
+                r.get::<_, i64>(0)?,
+                r.get::<_, NaiveDateTime>(1)?,
+                r.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    for (id, posted, tzutc) in rows {
+        if let Some(offset) = parse_tzutc(&tzutc) {
+            conn.execute(
+                "update messages set utc_offset = :offset, posted_utc = replace(:utc, 'T', ' ') where id = :id",
+                named_params! {
+                    ":id": id,
+                    ":offset": offset_minutes(offset),
+                    ":utc": posted - Duration::seconds(offset.local_minus_utc().into()),
+                },
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn offset_minutes(offset: FixedOffset) -> i32 {
+    offset.local_minus_utc() / 60
+}
@@ -1,23 +1,535 @@
-use std::collections::HashMap;
-use std::error::Error;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time;
 
-use crate::cfg::Config;
-use crate::core::Area;
-use crate::store::MessageBase;
+use chrono::{Duration, NaiveDateTime, Utc};
+use log::{error, info, warn};
+use rusqlite::ErrorCode;
+use zip::result::ZipError;
+
+use crate::badmail::BadMail;
+use crate::cfg::{Config, ConfigError, Inbound, Trust};
+use crate::core::{Address, Area, Message};
+use crate::error::{Error, Result, SecurityError};
+use crate::ftn::{Bundle, Package, PackageError, ParseError};
+use crate::store::{Journal, MessageBase, Position};
+
+mod runs;
+mod stats;
+
+pub(crate) use runs::RunLog;
+pub use stats::{AreaStats, Stats};
 
 enum InboundType {
     Package,
     Bundle,
 }
 
-pub fn toss(config: &Config) -> Result<(), Box<dyn Error>> {
-    let inbound = config.inbound.as_ref().unwrap().path.as_ref().unwrap();
-    let msgbase = Path::new(config.msgbase.as_ref().unwrap().path.as_ref().unwrap());
+/// Tosses packets and bundles from the inbound directories, oldest first, into the message base directory.
+/// Tossed files are removed, unreadable ones are moved to [`BadMail`], ones which failed for a reason
+/// likely to pass are left for the next run. A toss which has been interrupted resumes after the last
+/// message stored.
+///
+/// ```no_run
+/// use corona::cfg::{Config, Inbound, Msgbase};
+///
+/// let config = Config {
+///     inbound: Some(vec![Inbound {
+///         path: Some("/var/spool/ftn/inbound".to_string()),
+///         ..Default::default()
+///     }]),
+///     msgbase: Some(Msgbase {
+///         path: Some("/var/spool/ftn/msgbase".to_string()),
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// };
+///
+/// let stats = corona::tosser::toss(&config)?;
+/// println!("{stats}");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn toss(config: &Config) -> Result<Stats> {
+    toss_in(config, &mut Session::default())
+}
+
+/// State which outlives a single toss in a long-lived process
+#[derive(Default)]
+pub(crate) struct Session {
+    bases: HashMap<PathBuf, MessageBase>,
+    /// Once set, tossing stops after the file in progress
+    pub stop: Arc<AtomicBool>,
+}
+
+impl Session {
+    /// Closes the message bases, they are opened again as messages come
+    pub fn close(&mut self) {
+        self.bases.clear();
+    }
+}
+
+/// `toss` which keeps message bases open in `session` for the next one.
+/// Runs which have processed any file are stored in the [`RunLog`].
+pub(crate) fn toss_in(config: &Config, session: &mut Session) -> Result<Stats> {
+    let started = time::Instant::now();
+
+    if config.inbounds().is_empty() {
+        return Err(ConfigError::Invalid("inbound path is not set".to_string()).into());
+    }
+
+    let msgbase = config.msgbase_path()?;
+    let mut files = Vec::new();
+
+    for inbound in config.inbounds() {
+        let path = inbound
+            .path
+            .as_ref()
+            .ok_or_else(|| ConfigError::Invalid("inbound path is not set".to_string()))?;
+
+        files.extend(list_inbound(Path::new(path))?.into_iter().map(|f| (inbound, f)));
+    }
+
+    files.sort_by_key(|(_, (.., m))| *m);
+
+    check_free_space(config)?;
+
+    // the same name may come to several inbounds
+    let journal = Journal::open(&msgbase.join(JOURNAL))?;
+    let names: Vec<_> = files.iter().map(|(_, (p, ..))| p.to_string_lossy()).collect();
+    journal.retain(&names.iter().map(|n| n.as_ref()).collect::<Vec<_>>())?;
+
+    let mut run = Run {
+        config,
+        msgbase,
+        journal,
+        bad: BadMail::new(&bad_mail_path(config)?),
+        bases: &mut session.bases,
+        stats: Stats::start(),
+    };
+
+    let res = run.forget_finished().and_then(|_| run.toss_all(files, &session.stop));
+    let mut stats = run.stats;
+    stats.duration_ms = started.elapsed().as_millis() as u64;
+
+    // messages stored before a failure are traffic too
+    let logged = match stats.packets + stats.bundles {
+        0 => Ok(()),
+        _ => RunLog::open(&msgbase.join(RUN_LOG)).and_then(|log| log.record(&stats)),
+    };
+
+    res.and(logged.map_err(Error::from))?;
+
+    if stats.packets + stats.bundles > 0 {
+        info!(
+            "{} message(s), {} dupe(s) from {} packet(s) and {} bundle(s), {} quarantined, {} bad",
+            stats.messages(),
+            stats.dupes(),
+            stats.packets,
+            stats.bundles,
+            stats.quarantined,
+            stats.bad_files
+        );
+    }
+
+    Ok(stats)
+}
+
+impl Run<'_> {
+    /// Counts files which are busy or still being written as left
+    fn toss_all(&mut self, files: Vec<(&Inbound, InboundFile)>, stop: &AtomicBool) -> Result<()> {
+        let mut busy = Vec::new();
+
+        for (inbound, (path, ty, len, modified)) in files {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            if let Some(flag) = inbound.busy_flag().filter(|f| busy.contains(f) || f.exists()) {
+                if !busy.contains(&flag) {
+                    info!("Inbound is busy while {:?} exists", flag);
+                    busy.push(flag);
+                }
+
+                self.stats.left += 1;
+                continue;
+            }
+
+            check_free_space(self.config)?;
+
+            if !settled(&path, len, modified, inbound.settle_time()) {
+                info!("skipping {:?}, it is still being written", path);
+                self.stats.left += 1;
+                continue;
+            }
+
+            info!("tossing {:?}", path);
+
+            if self.toss(inbound, &path, &ty, len, modified)? == Outcome::Left {
+                self.stats.left += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Tosses files from bad mail again, e.g. after a parser fix or a config change, with the settings
+/// of the inbounds they came from. `file` is the name of one file in bad mail, all of them if `None`.
+/// Files which toss now are removed with their records. Returns how many files are still bad.
+pub fn retoss(config: &Config, file: Option<&str>) -> Result<usize> {
+    let msgbase = config.msgbase_path()?;
+    let bad = BadMail::new(&bad_mail_path(config)?);
+    let list = bad.list()?;
+
+    let list: Vec<_> = match file {
+        Some(name) => list.into_iter().filter(|(p, _)| file_name_ext(p).0 == name).collect(),
+        None => list,
+    };
+
+    if let (Some(name), true) = (file, list.is_empty()) {
+        warn!("There is no bad file {name}");
+    }
+
+    let mut session = Session::default();
+    let mut run = Run {
+        config,
+        msgbase,
+        journal: Journal::open(&msgbase.join(JOURNAL))?,
+        bad,
+        bases: &mut session.bases,
+        stats: Stats::start(),
+    };
+    let mut left = 0;
+
+    for (path, record) in list {
+        let inbound = config
+            .inbounds()
+            .iter()
+            .find(|i| i.path.as_deref() == Some(record.inbound.as_str()));
+
+        let (Some(inbound), Some(ty)) = (inbound, inbound_type(Path::new(&record.file))) else {
+            warn!(
+                "Skipping \"{}\", it came to {} which is no longer an inbound",
+                file_name_ext(&path).0,
+                record.inbound
+            );
+            left += 1;
+            continue;
+        };
+
+        check_free_space(config)?;
+
+        info!("retossing {:?}", path);
+
+        let m = fs::metadata(&path)?;
+
+        match run.toss(inbound, &path, &ty, m.len(), m.modified()?)? {
+            Outcome::Tossed => run.bad.clear(&path)?,
+            Outcome::Bad | Outcome::Left => left += 1,
+        }
+    }
+
+    Ok(left)
+}
+
+/// What has become of an inbound file
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// Removed from inbound
+    Tossed,
+    /// Moved to bad mail
+    Bad,
+    /// Left where it was for a later run
+    Left,
+}
+
+/// Where the files of a toss go
+struct Run<'a> {
+    config: &'a Config,
+    msgbase: &'a Path,
+    journal: Journal,
+    bad: BadMail,
+    bases: &'a mut HashMap<PathBuf, MessageBase>,
+    stats: Stats,
+}
+
+impl Run<'_> {
+    fn toss(
+        &mut self,
+        inbound: &Inbound,
+        path: &Path,
+        ty: &InboundType,
+        len: u64,
+        modified: time::SystemTime,
+    ) -> Result<Outcome> {
+        let (id, resumed) = self.journal.start(&path.to_string_lossy(), len, modified)?;
+
+        if resumed {
+            info!("resuming after the last message stored");
+        }
+
+        let mut tries = 0;
+
+        let res = loop {
+            let mut progress = Progress {
+                pos: Position {
+                    inbound: id,
+                    member: 0,
+                    offset: 0,
+                },
+                resuming: resumed || tries > 0,
+                damage: Vec::new(),
+                bases: HashSet::new(),
+            };
+
+            let res = self.toss_file(path, ty, &mut progress, inbound);
+
+            match res.as_ref().map_err(Policy::of) {
+                Err(Policy::Retry) if tries < RETRIES => {
+                    tries += 1;
+                    thread::sleep(RETRY_PAUSE);
+                }
+                _ => break (res, progress.damage),
+            }
+        };
+
+        let (res, damage) = res;
+
+        if !matches!(res.as_ref().map_err(Policy::of), Err(Policy::Retry)) {
+            match ty {
+                InboundType::Package => self.stats.packets += 1,
+                InboundType::Bundle => self.stats.bundles += 1,
+            }
+
+            self.stats.bytes += len;
+        }
+
+        let outcome = match res {
+            // remove package or bundle if everything is ok
+            Ok(()) if damage.is_empty() => {
+                fs::remove_file(path)?;
+                Outcome::Tossed
+            }
+            // what could be read is stored, the file is kept as the evidence of the damage
+            Ok(()) => {
+                self.bad_mail(path, inbound, &damage.join("; "))?;
+                Outcome::Bad
+            }
+            Err(e) => match Policy::of(&e) {
+                Policy::Retry => {
+                    warn!("Failed to toss \"{}\", will retry later: {}", file_name_ext(path).0, e);
+                    return Ok(Outcome::Left);
+                }
+                Policy::Quarantine => {
+                    error!("Failed to read \"{}\", reason: {}", file_name_ext(path).0, e);
+                    self.bad_mail(path, inbound, &e)?;
+                    Outcome::Bad
+                }
+                Policy::Abort => return Err(e),
+            },
+        };
+
+        self.journal.finish(id)?;
+        self.forget_finished()?;
+
+        Ok(outcome)
+    }
+
+    /// Drops the positions of files which are gone from inbound, also from the bases which
+    /// haven't been opened by this run
+    fn forget_finished(&mut self) -> Result<()> {
+        for (id, path) in self.journal.stale_positions()? {
+            match self.bases.get(&path) {
+                Some(mb) => mb.forget(id)?,
+                None if path.exists() => MessageBase::open(&path)?.forget(id)?,
+                None => {}
+            }
+
+            self.journal.forgotten(id, &path)?;
+        }
+
+        Ok(())
+    }
+
+    /// The journal learns of a base before the base keeps a position of the file
+    fn stored_in(&self, path: PathBuf, progress: &mut Progress) -> Result<()> {
+        if !progress.bases.contains(&path) {
+            self.journal.stored_in(progress.pos.inbound, &path)?;
+            progress.bases.insert(path);
+        }
+
+        Ok(())
+    }
+
+    fn bad_mail(&mut self, path: &Path, inbound: &Inbound, reason: &dyn fmt::Display) -> Result<()> {
+        let origin = inbound.path.as_deref().unwrap_or_default();
+        let bad = self.bad.put(path, origin, reason)?;
+
+        if bad != path {
+            info!("moved to {:?}", bad);
+        }
+
+        self.stats.bad_files += 1;
+
+        Ok(())
+    }
+
+    fn toss_file(&mut self, path: &Path, ty: &InboundType, progress: &mut Progress, inbound: &Inbound) -> Result<()> {
+        // a file which can't be opened is as bad as one which can't be read
+        let file = File::open(path).map_err(|e| -> Error {
+            match ty {
+                InboundType::Package => PackageError::Io { offset: 0, source: e }.into(),
+                InboundType::Bundle => ParseError::Bundle(ZipError::Io(e)).into(),
+            }
+        })?;
+
+        match ty {
+            InboundType::Package => self.toss_package(Package::read(file)?, progress, inbound),
+            InboundType::Bundle => self.toss_bundle(file, progress, inbound),
+        }
+    }
+
+    /// Messages are stored as soon as they are read. In lenient mode the damage of a packet is kept
+    /// in `progress`, the file then goes to bad mail once the rest has been stored.
+    fn toss_package(&mut self, mut pkg: Package<impl Read>, progress: &mut Progress, inbound: &Inbound) -> Result<()> {
+        pkg = pkg
+            .with_max_message_size(inbound.max_message_size())
+            .with_lenient(inbound.lenient());
+
+        let link = self.config.qualify(&pkg.header.orig.clone().into());
+
+        check_link(&link, pkg.header.password(), self.config, inbound)?;
+
+        while let Some(m) = pkg.next() {
+            let msg = crate::core::message_from(&pkg.header, m?, |area| self.config.keep_raw(area))?;
+            progress.pos.offset = pkg.offset();
+            self.toss_message(msg, &link, progress, inbound)?;
+        }
+
+        for e in pkg.damage() {
+            warn!("damaged packet. {}", e);
+            progress.damage.push(e.to_string());
+        }
+
+        Ok(())
+    }
+
+    fn toss_bundle(&mut self, file: File, progress: &mut Progress, inbound: &Inbound) -> Result<()> {
+        let mut bundle = Bundle::read(file)?;
+
+        for i in 0..bundle.file_count() {
+            // TODO: handle the case when one PKG in a bundle is corrupted, while others - don't
+            if let Some(pkg) = bundle.package(i)? {
+                progress.pos.member = i;
+                self.toss_package(pkg, progress, inbound)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Messages which fail the date check, have an invalid echo tag or aren't accepted from `inbound`
+    /// go to the quarantine base instead of their areas
+    fn toss_message(
+        &mut self,
+        mut msg: Message,
+        link: &Address,
+        progress: &mut Progress,
+        inbound: &Inbound,
+    ) -> Result<()> {
+        let (config, msgbase) = (self.config, self.msgbase);
+
+        let base = msg.area.base_name();
+        let bad_tag = base.is_none();
+        let db_path = msgbase.join(base.unwrap_or_default());
 
-    let mut inbound: Vec<_> = fs::read_dir(inbound)?
+        let pos = progress.pos;
+
+        if progress.resuming {
+            let quarantine = msgbase.join(QUARANTINE);
+
+            // the date check might have given another answer last time, a base which doesn't exist
+            // keeps no position and mustn't be created for a message a fresh toss would quarantine
+            let in_area = !bad_tag && (self.bases.contains_key(&db_path) || db_path.exists());
+
+            if (quarantine.exists() && open_base(self.bases, quarantine, true)?.tossed(&pos)?)
+                || (in_area && open_base(self.bases, db_path.clone(), config.keep_raw(&msg.area))?.tossed(&pos)?)
+            {
+                return Ok(());
+            }
+
+            progress.resuming = false;
+        }
+
+        msg.from.addr = config.qualify(&msg.from.addr);
+        msg.to.addr = config.qualify(&msg.to.addr);
+
+        let limits = DateLimits {
+            max_age: inbound.max_age(),
+            max_future: inbound.max_future_skew(),
+        };
+
+        let checked = match &msg.area {
+            Area::Echomail(name) if bad_tag => Err(format!("{name} is not a valid echo tag")),
+            Area::Echomail(name) if !inbound.accepts(&msg.area) => {
+                Err(format!("{name} is not accepted from an insecure inbound"))
+            }
+            _ => limits.check(msg.posted, msg.posted_utc(), Utc::now().naive_utc()),
+        };
+
+        if let Err(reason) = checked {
+            self.stored_in(msgbase.join(QUARANTINE), progress)?;
+            let mb = open_base(self.bases, msgbase.join(QUARANTINE), true)?;
+
+            let serial = msg.msgid_serial;
+
+            if mb.quarantine(&pos, msg, link, &reason)? > 0 {
+                warn!("Message ({:08x}) from {} quarantined because {}", serial, link, reason);
+                self.stats.quarantined += 1;
+            }
+
+            return Ok(());
+        }
+
+        let new = !self.bases.contains_key(&db_path) && !db_path.exists();
+        self.stored_in(db_path.clone(), progress)?;
+
+        let area = self.stats.area(&msg.area);
+        area.new |= new;
+
+        let mb = open_base(self.bases, db_path, config.keep_raw(&msg.area))?;
+
+        let reply = crate::netmail::track(config, &msg);
+        crate::netmail::stamp(config, &mut msg);
+
+        match mb.toss_from(&pos, msg, reply)? {
+            -1 => area.dupes += 1,
+            _ => area.messages += 1,
+        }
+
+        Ok(())
+    }
+}
+
+fn bad_mail_path(config: &Config) -> Result<PathBuf> {
+    Ok(config
+        .bad_mail_path()
+        .ok_or_else(|| ConfigError::Invalid("msgbase path is not set".to_string()))?)
+}
+
+/// Path, type, size and modification time
+type InboundFile = (PathBuf, InboundType, u64, time::SystemTime);
+
+/// Packets and bundles in the directory `path`
+fn list_inbound(path: &Path) -> Result<Vec<InboundFile>> {
+    let files = fs::read_dir(path)?
         .filter_map(|e| e.ok())
         .filter_map(|e| {
             if let Ok(m) = e.metadata() {
@@ -28,84 +540,157 @@ pub fn toss(config: &Config) -> Result<(), Box<dyn Error>> {
         })
         .filter(|(_, m)| m.is_file())
         .filter(|(_, m)| m.len() > 64)
-        .filter_map(|(p, m)| {
-            let (name, ext) = file_name_ext(&p);
-
-            if name.is_empty() || ext.len() != 3 {
-                return None;
-            }
-
-            match (&ext[..2], &ext[2..]) {
-                ("pk", "t") => Some((p, InboundType::Package, m)),
-                ("su" | "mo" | "tu" | "we" | "th" | "fr" | "sa", _) => Some((p, InboundType::Bundle, m)),
-                _ => None,
-            }
-        })
+        .filter(|(p, _)| !temp_name(file_name_ext(p).0))
+        .filter_map(|(p, m)| Some((inbound_type(&p)?, p, m)))
+        .map(|(t, p, m)| (p, t, m))
         .filter_map(|(p, t, m)| {
             if let Ok(tmod) = m.modified() {
-                Some((p, t, tmod))
+                Some((p, t, m.len(), tmod))
             } else {
                 None
             }
         })
         .collect();
 
-    inbound.sort_by(|(_, _, mx), (_, _, my)| mx.cmp(my));
-
-    let mut bases = HashMap::new();
-
-    for (path, ty, _) in inbound {
-        match File::open(&path) {
-            Ok(file) => {
-                match ty {
-                    InboundType::Package => {
-                        println!("tossing {:?}", path);
-
-                        fn get_messages(file: &File) -> Result<Vec<crate::core::Message>, Box<dyn Error>> {
-                            crate::core::messages_from(crate::ftn::Package::read(file)?)
-                        }
-
-                        match get_messages(&file) {
-                            Ok(msgs) => {
-                                toss_messages(msgs, msgbase, &mut bases)?;
-
-                                // remove package if everything is ok
-                                fs::remove_file(&path)?;
-                            }
-                            Err(e) => {
-                                bad_mail(&path, e)?;
-                            }
-                        }
-                    }
-                    InboundType::Bundle => {
-                        println!("tossing {:?}", path);
-
-                        match crate::ftn::Bundle::read(file) {
-                            Ok(bundle) => {
-                                for pkg in bundle.packages {
-                                    // TODO: handle the case when one PKG in a bundle is corrupted, while others - don't
-                                    toss_messages(crate::core::messages_from(pkg)?, msgbase, &mut bases)?;
-                                }
-
-                                // remove bundle if everything is ok
-                                fs::remove_file(&path)?;
-                            }
-                            Err(e) => {
-                                bad_mail(&path, e)?;
-                            }
-                        }
-                    }
-                }
+    Ok(files)
+}
+
+/// Packet or bundle, by the name of the file
+fn inbound_type(path: &Path) -> Option<InboundType> {
+    let (name, ext) = file_name_ext(path);
+
+    if name.is_empty() || ext.len() != 3 {
+        return None;
+    }
+
+    match (&ext[..2], &ext[2..]) {
+        ("pk", "t") => Some(InboundType::Package),
+        ("su" | "mo" | "tu" | "we" | "th" | "fr" | "sa", _) => Some(InboundType::Bundle),
+        _ => None,
+    }
+}
+
+/// Journal of inbound files, next to the message bases
+const JOURNAL: &str = "inbound.journal";
+/// Statistics of toss runs, next to the message bases
+const RUN_LOG: &str = "toss.runs";
+
+/// Position of the message being tossed. While `resuming`, messages which have been stored
+/// before the toss was interrupted are skipped.
+struct Progress {
+    pos: Position,
+    resuming: bool,
+    /// Damage of the packets read in lenient mode
+    damage: Vec<String>,
+    /// Message bases known to the journal to keep a position of the file
+    bases: HashSet<PathBuf>,
+}
+
+/// How many times a file is tossed again right away before it is left for the next run
+const RETRIES: u32 = 3;
+const RETRY_PAUSE: time::Duration = time::Duration::from_secs(1);
+
+/// What to do with an inbound file which failed to toss. Messages tossed before the failure
+/// stay in the message base, they are skipped as dupes when the file is tossed again.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Policy {
+    /// Leave the file in inbound, the failure is likely temporary
+    Retry,
+    /// Move the file to bad mail, tossing it again won't help
+    Quarantine,
+    /// Stop tossing, the following files would fail the same way
+    Abort,
+}
+
+impl Policy {
+    /// Only failures which are known to pass are retried. A file which can't be read goes to bad
+    /// mail, other file system failures stop the toss.
+    pub fn of(e: &Error) -> Self {
+        match e {
+            Error::Parse(ParseError::Package(PackageError::Io { source: e, .. }))
+            | Error::Parse(ParseError::Bundle(ZipError::Io(e)))
+                if transient(e) =>
+            {
+                Self::Retry
             }
-            Err(e) => {
-                eprintln!("Failed to open \"{}\", reason: {}", file_name_ext(&path).0, e);
+            Error::Parse(_) | Error::Decode(_) | Error::Security(_) => Self::Quarantine,
+            Error::Store(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) =>
+            {
+                Self::Retry
             }
+            Error::Io(e) if transient(e) => Self::Retry,
+            Error::Io(_)
+            | Error::Store(_)
+            | Error::Config(_)
+            | Error::Locked(_)
+            | Error::NotFound(_)
+            | Error::NoSpace { .. } => Self::Abort,
+        }
+    }
+}
+
+fn transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Packets from local tools carry no password, the rest must have the one of their link
+fn check_link(
+    link: &Address,
+    password: &str,
+    config: &Config,
+    inbound: &Inbound,
+) -> std::result::Result<(), SecurityError> {
+    if !inbound.allowed(link) {
+        return Err(SecurityError::LinkNotAllowed(link.clone()));
+    }
+
+    match config.password(link) {
+        Some(p) if inbound.trust() != Trust::Local && !p.eq_ignore_ascii_case(password) => {
+            Err(SecurityError::WrongPassword(link.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Stops before the message base or an extracted packet is written half way onto a full disk
+fn check_free_space(config: &Config) -> Result<()> {
+    for (path, needed) in config.min_free_space() {
+        let free = fs2::available_space(path)?;
+
+        if free < needed {
+            return Err(Error::NoSpace {
+                path: path.to_path_buf(),
+                free,
+                needed,
+            });
         }
     }
 
     Ok(())
 }
 
+/// Names mailers give to files they are receiving. Temporary extensions, such as `.tmp` or `.hr`,
+/// are never taken for a packet or a bundle by [`inbound_type`].
+fn temp_name(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with('~')
+}
+
+/// Whether the file still has the size and the modification time it had when inbound was read,
+/// and nobody has written to it for `settle`. A modification time in the future tells nothing
+/// of when the file has been written, so such a file waits until that time has passed.
+fn settled(path: &Path, len: u64, modified: time::SystemTime, settle: time::Duration) -> bool {
+    match fs::metadata(path) {
+        Ok(m) if m.len() == len && m.modified().ok() == Some(modified) => {
+            modified.elapsed().is_ok_and(|age| age >= settle)
+        }
+        _ => false,
+    }
+}
+
 fn file_name_ext(path: &Path) -> (&str, &str) {
     (
         path.file_name().map_or("", |x| x.to_str().unwrap_or("")),
@@ -113,33 +698,396 @@ fn file_name_ext(path: &Path) -> (&str, &str) {
     )
 }
 
-fn bad_mail(path: &Path, err: Box<dyn Error>) -> Result<(), Box<dyn Error>> {
-    let (name, ext) = file_name_ext(path);
+/// Message bases are opened once per toss
+fn open_base(bases: &mut HashMap<PathBuf, MessageBase>, path: PathBuf, keep_raw: bool) -> Result<&MessageBase> {
+    match bases.entry(path) {
+        Entry::Occupied(e) => Ok(e.into_mut()),
+        Entry::Vacant(e) => {
+            let mut mb = MessageBase::open(e.key())?;
+            mb.keep_raw(keep_raw);
+            Ok(e.insert(mb))
+        }
+    }
+}
 
-    eprintln!("Failed to read \"{}\", reason: {}", name, err);
+/// Base for messages with implausible dates, from all areas. Its name is not a valid echo tag, so it
+/// never collides with an area base.
+pub const QUARANTINE: &str = ".quarantine";
 
-    fs::rename(path, Path::new(&path).with_extension(ext.to_string() + ".bad"))?;
+/// Zones are within 14 hours from UTC, a date of unknown zone gets that much slack
+const ZONE_SLACK_HOURS: i64 = 14;
 
-    Ok(())
+/// Posted dates out of these limits come from broken clocks or replayed traffic
+struct DateLimits {
+    max_age: Option<Duration>,
+    max_future: Option<Duration>,
 }
 
-fn toss_messages(
-    inbound: Vec<crate::core::Message>,
-    msgbase: &Path,
-    bases: &mut HashMap<PathBuf, MessageBase>,
-) -> Result<(), Box<dyn Error>> {
-    for msg in inbound {
-        let db_path = match msg.area {
-            Area::Netmail => msgbase.join("netmail"),
-            Area::Echomail(ref name) => msgbase.join(&name.to_ascii_lowercase()),
+impl DateLimits {
+    fn check(
+        &self,
+        posted: NaiveDateTime,
+        posted_utc: Option<NaiveDateTime>,
+        now_utc: NaiveDateTime,
+    ) -> std::result::Result<(), String> {
+        let (utc, slack) = match posted_utc {
+            Some(utc) => (utc, Duration::zero()),
+            None => (posted, Duration::hours(ZONE_SLACK_HOURS)),
         };
 
-        let mb = bases
-            .entry(db_path.clone())
-            .or_insert_with(|| MessageBase::open(&db_path).unwrap());
+        if let Some(age) = self.max_age {
+            if utc + slack < now_utc - age {
+                return Err(format!("posted {} is more than {} day(s) ago", posted, age.num_days()));
+            }
+        }
+
+        if let Some(skew) = self.max_future {
+            if utc - slack > now_utc + skew {
+                return Err(format!("posted {} is in the future", posted));
+            }
+        }
 
-        mb.toss(msg)?;
+        Ok(())
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::{
+        check_link, inbound_type, settled, temp_name, toss_in, DateLimits, Policy, Progress, Run, Session, Stats,
+        JOURNAL,
+    };
+    use crate::badmail::BadMail;
+    use crate::cfg::{Config, ConfigError};
+    use crate::error::{Error, SecurityError};
+    use crate::ftn::{Package, PackageError, ParseError};
+    use crate::store::{Journal, Position};
+    use chrono::{Duration, NaiveDate};
+    use rusqlite::Connection;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+    use zip::result::ZipError;
+
+    #[test]
+    fn check_date_limits() {
+        let now = NaiveDate::from_ymd(2020, 2, 28).and_hms(12, 0, 0);
+        let at = |d, h| NaiveDate::from_ymd(2020, 2, d).and_hms(h, 0, 0);
+
+        let limits = DateLimits {
+            max_age: Some(Duration::days(7)),
+            max_future: Some(Duration::minutes(30)),
+        };
+
+        assert!(limits.check(at(28, 12), Some(at(28, 12)), now).is_ok());
+        assert!(limits.check(at(21, 13), Some(at(21, 13)), now).is_ok());
+        assert!(limits.check(at(21, 11), Some(at(21, 11)), now).is_err());
+        assert!(limits.check(at(28, 13), Some(at(28, 13)), now).is_err());
+
+        // unknown zone
+        assert!(limits.check(at(28, 23), None, now).is_ok());
+        assert!(limits.check(at(21, 0), None, now).is_ok());
+        assert!(limits.check(at(29, 3), None, now).is_err());
+        assert!(limits.check(at(20, 20), None, now).is_err());
+
+        let none = DateLimits {
+            max_age: None,
+            max_future: None,
+        };
+
+        assert!(none.check(at(1, 0), None, now).is_ok());
+    }
+
+    #[test]
+    fn error_policies() {
+        let io = |kind| Error::Io(io::Error::from(kind));
+        let sqlite = |code| Error::Store(rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None));
+
+        assert_eq!(Policy::of(&io(io::ErrorKind::Interrupted)), Policy::Retry);
+        assert_eq!(Policy::of(&io(io::ErrorKind::TimedOut)), Policy::Retry);
+        assert_eq!(Policy::of(&io(io::ErrorKind::PermissionDenied)), Policy::Abort);
+        assert_eq!(Policy::of(&io(io::ErrorKind::NotFound)), Policy::Abort);
+        assert_eq!(Policy::of(&io(io::ErrorKind::StorageFull)), Policy::Abort);
+
+        let damaged = PackageError::MissingTerminator { offset: 100 };
+        let unreadable = |kind| PackageError::Io {
+            offset: 100,
+            source: io::Error::from(kind),
+        };
+        assert_eq!(Policy::of(&damaged.into()), Policy::Quarantine);
+        assert_eq!(Policy::of(&unreadable(io::ErrorKind::WouldBlock).into()), Policy::Retry);
+        assert_eq!(Policy::of(&unreadable(io::ErrorKind::Other).into()), Policy::Quarantine);
+        assert_eq!(
+            Policy::of(&unreadable(io::ErrorKind::PermissionDenied).into()),
+            Policy::Quarantine
+        );
+
+        // packets and bundles are alike
+        let bundle = |kind| Error::Parse(ParseError::Bundle(ZipError::Io(io::Error::from(kind))));
+        assert_eq!(Policy::of(&bundle(io::ErrorKind::Interrupted)), Policy::Retry);
+        assert_eq!(Policy::of(&bundle(io::ErrorKind::Other)), Policy::Quarantine);
+
+        let link = "2:5020/100".parse().unwrap();
+        assert_eq!(
+            Policy::of(&SecurityError::LinkNotAllowed(link).into()),
+            Policy::Quarantine
+        );
+
+        assert_eq!(Policy::of(&sqlite(rusqlite::ffi::SQLITE_BUSY)), Policy::Retry);
+        assert_eq!(Policy::of(&sqlite(rusqlite::ffi::SQLITE_LOCKED)), Policy::Retry);
+        assert_eq!(Policy::of(&sqlite(rusqlite::ffi::SQLITE_FULL)), Policy::Abort);
+        assert_eq!(Policy::of(&sqlite(rusqlite::ffi::SQLITE_CORRUPT)), Policy::Abort);
+
+        let config = ConfigError::Invalid("msgbase path is not set".to_string());
+        assert_eq!(Policy::of(&config.into()), Policy::Abort);
+
+        let full = Error::NoSpace {
+            path: "/var/spool/ftn".into(),
+            free: 1 << 20,
+            needed: 16 << 20,
+        };
+        assert_eq!(Policy::of(&full), Policy::Abort);
+        assert!(full.is_no_space());
+        assert!(io(io::ErrorKind::StorageFull).is_no_space());
+        assert!(sqlite(rusqlite::ffi::SQLITE_FULL).is_no_space());
+        assert!(!sqlite(rusqlite::ffi::SQLITE_BUSY).is_no_space());
+    }
+
+    #[test]
+    fn skip_files_being_written() {
+        assert!(temp_name(".0000abcd.pkt"));
+        assert!(temp_name("~0000abcd.mo0"));
+        assert!(!temp_name("0000abcd.pkt"));
+        assert!(!temp_name("0000abcd.we1"));
+
+        for name in [
+            "0000abcd.pkt.tmp",
+            "0000ABCD.$$$",
+            "1f2e3d4c.hr",
+            "1f2e3d4c.dt",
+            "0000abcd.pkt.part",
+        ] {
+            assert!(inbound_type(Path::new(name)).is_none());
+        }
+
+        let path = std::env::temp_dir().join(format!("corona-settled-{}.pkt", std::process::id()));
+        std::fs::write(&path, b"packet").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        assert!(settled(&path, 6, modified, std::time::Duration::ZERO));
+        assert!(!settled(&path, 6, modified, std::time::Duration::from_secs(3600)));
+        assert!(!settled(&path, 5, modified, std::time::Duration::ZERO));
+
+        let future = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(future)
+            .unwrap();
+        assert!(!settled(&path, 6, future, std::time::Duration::ZERO));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!settled(&path, 6, modified, std::time::Duration::ZERO));
+    }
+
+    #[test]
+    fn trust_policies() {
+        let config: Config = r#"
+            [[inbound]]
+            path = "/var/spool/ftn/inbound"
+            allow = ["2:5020/*"]
+
+            [[inbound]]
+            path = "/var/spool/ftn/local"
+            trust = "local"
+
+            [[link]]
+            address = "2:5020/100"
+            password = "secret"
+        "#
+        .parse()
+        .unwrap();
+
+        let [secure, local] = config.inbounds() else {
+            panic!("two inbounds expected")
+        };
+        let link = "2:5020/100".parse().unwrap();
+        let other = "2:5030/100".parse().unwrap();
+
+        assert!(check_link(&link, "SECRET", &config, secure).is_ok());
+        assert!(matches!(
+            check_link(&link, "", &config, secure),
+            Err(SecurityError::WrongPassword(_))
+        ));
+        assert!(matches!(
+            check_link(&other, "", &config, secure),
+            Err(SecurityError::LinkNotAllowed(_))
+        ));
+
+        assert!(check_link(&link, "", &config, local).is_ok());
+        assert!(check_link(&other, "", &config, local).is_ok());
+    }
+
+    /// Type 2 packet from 2:5020/100 to 2:5020/1
+    fn packet(messages: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for v in [100u16, 1, 2020, 1, 28, 14, 0, 18, 0, 2, 5020, 5020] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+
+        data.extend_from_slice(&[0; 2 + 8]); // product, serial, password
+        data.extend_from_slice(&2u16.to_le_bytes()); // orig_zone
+        data.extend_from_slice(&2u16.to_le_bytes()); // dest_zone
+        data.extend_from_slice(&[0; 20]);
+
+        for m in messages {
+            data.extend_from_slice(m);
+        }
+
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data
+    }
+
+    fn echomail(area: &str, serial: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for v in [2u16, 100, 1, 5020, 5020, 0, 0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+
+        data.extend_from_slice(b"28 Feb 20  14:00:18\0All\0John Doe\0Ping\0");
+        data.extend_from_slice(
+            format!("AREA:{area}\r\x01MSGID: 2:5020/100 {serial:08x}\rPong\r * Origin: Test (2:5020/100)\r\0")
+                .as_bytes(),
+        );
+
+        data
+    }
+
+    fn count(base: &Path, table: &str) -> i64 {
+        Connection::open(base)
+            .unwrap()
+            .query_row(&format!("select count(*) from {table}"), [], |r| r.get(0))
+            .unwrap()
+    }
+
+    /// Tosses the first `n` messages of the inbound file `path` as a toss which has been interrupted
+    fn toss_partly(config: &Config, path: &Path, messages: &[&[u8]], n: usize) {
+        let msgbase = config.msgbase_path().unwrap();
+        let mut session = Session::default();
+        let mut run = Run {
+            config,
+            msgbase,
+            journal: Journal::open(&msgbase.join(JOURNAL)).unwrap(),
+            bad: BadMail::new(&msgbase.join("badmail")),
+            bases: &mut session.bases,
+            stats: Stats::start(),
+        };
+
+        let m = fs::metadata(path).unwrap();
+        let (id, _) = run
+            .journal
+            .start(&path.to_string_lossy(), m.len(), m.modified().unwrap())
+            .unwrap();
+
+        let mut progress = Progress {
+            pos: Position {
+                inbound: id,
+                member: 0,
+                offset: 0,
+            },
+            resuming: false,
+            damage: Vec::new(),
+            bases: HashSet::new(),
+        };
+
+        // the messages have the offsets they have in the whole packet
+        let data = packet(&messages[..n]);
+        let pkg = Package::read(&data[..]).unwrap();
+        run.toss_package(pkg, &mut progress, &config.inbounds()[0]).unwrap();
+    }
+
+    /// Inbound and message bases in `root`, `inbound` adds to the settings of the inbound
+    fn test_config(root: &Path, inbound: &str) -> Config {
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("msgbase")).unwrap();
+
+        format!(
+            r#"
+            [inbound]
+            path = "{}"
+            settle_seconds = 0
+            {inbound}
+            [msgbase]
+            path = "{}"
+            "#,
+            root.join("in").display(),
+            root.join("msgbase").display()
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn resume_interrupted_packet() {
+        let root = std::env::temp_dir().join(format!("corona-resume-{}", std::process::id()));
+        let config = test_config(&root, "");
+        let (inbound, msgbase) = (root.join("in"), root.join("msgbase"));
+
+        let (a, b) = (msgbase.join("area.a"), msgbase.join("area.b"));
+
+        let messages = [
+            &echomail("AREA.A", 1)[..],
+            &echomail("AREA.B", 2)[..],
+            &echomail("AREA.A", 3)[..],
+        ];
+        let path = inbound.join("00000001.pkt");
+        fs::write(&path, packet(&messages)).unwrap();
+
+        toss_partly(&config, &path, &messages, 2);
+        assert_eq!((count(&a, "messages"), count(&b, "messages")), (1, 1));
+
+        let stats = toss_in(&config, &mut Session::default()).unwrap();
+        assert_eq!((stats.messages(), stats.dupes()), (1, 0));
+        assert_eq!((count(&a, "messages"), count(&b, "messages")), (2, 1));
+        assert!(!path.exists());
+        assert_eq!(count(&a, "toss_positions") + count(&b, "toss_positions"), 0);
+
+        // removed from inbound half way, its position goes from a base this toss doesn't open
+        let messages = [&echomail("AREA.B", 4)[..], &echomail("AREA.B", 5)[..]];
+        fs::write(&path, packet(&messages)).unwrap();
+        toss_partly(&config, &path, &messages, 1);
+        assert_eq!(count(&b, "toss_positions"), 1);
+
+        fs::remove_file(&path).unwrap();
+        fs::write(inbound.join("00000002.pkt"), packet(&[&echomail("AREA.A", 6)])).unwrap();
+        let stats = toss_in(&config, &mut Session::default()).unwrap();
+        assert_eq!(stats.areas.keys().collect::<Vec<_>>(), ["AREA.A"]);
+        assert_eq!(count(&b, "toss_positions"), 0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resume_into_quarantine() {
+        let root = std::env::temp_dir().join(format!("corona-resume-quarantine-{}", std::process::id()));
+        let config = test_config(&root, "max_age_days = 1");
+        let msgbase = root.join("msgbase");
+
+        let messages = [&echomail("AREA.C", 1)[..], &echomail("AREA.C", 2)[..]];
+        let path = root.join("in/00000001.pkt");
+        fs::write(&path, packet(&messages)).unwrap();
+
+        toss_partly(&config, &path, &messages, 1);
+        let stats = toss_in(&config, &mut Session::default()).unwrap();
+
+        assert_eq!(stats.quarantined, 1);
+        assert_eq!(count(&msgbase.join(".quarantine"), "messages"), 2);
+        assert!(!msgbase.join("area.c").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
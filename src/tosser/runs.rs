@@ -0,0 +1,127 @@
+use rusqlite::{named_params, Connection, Result};
+use std::path::Path;
+
+use super::Stats;
+
+/// Statistics of toss runs, kept to chart traffic over time
+pub(crate) struct RunLog {
+    conn: Connection,
+}
+
+impl RunLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        conn.execute_batch(
+            r#"
+            create table if not exists toss_runs (
+                id              integer primary key,
+                started         text not null,
+                duration_ms     integer not null,
+                packets         integer not null,
+                bundles         integer not null,
+                bytes           integer not null,
+                messages        integer not null,
+                dupes           integer not null,
+                quarantined     integer not null,
+                bad_files       integer not null,
+                left_files      integer not null
+            );
+
+            create table if not exists toss_run_areas (
+                run_id          integer not null references toss_runs(id) on delete cascade,
+                area            text not null,
+                messages        integer not null,
+                dupes           integer not null,
+                new             integer not null,
+                primary key (run_id, area)
+            );
+            "#,
+        )?;
+
+        Ok(Self { conn })
+    }
+
+    pub fn record(&self, stats: &Stats) -> Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+
+        tx.execute(
+            r#"
+            insert into toss_runs (
+                started, duration_ms, packets, bundles, bytes, messages, dupes, quarantined, bad_files, left_files
+            ) values (
+                :started, :duration_ms, :packets, :bundles, :bytes, :messages, :dupes, :quarantined, :bad_files, :left
+            )
+            "#,
+            named_params! {
+                ":started": stats.started,
+                ":duration_ms": stats.duration_ms,
+                ":packets": stats.packets,
+                ":bundles": stats.bundles,
+                ":bytes": stats.bytes,
+                ":messages": stats.messages(),
+                ":dupes": stats.dupes(),
+                ":quarantined": stats.quarantined,
+                ":bad_files": stats.bad_files,
+                ":left": stats.left,
+            },
+        )?;
+
+        let id = tx.last_insert_rowid();
+
+        for (area, a) in &stats.areas {
+            tx.execute(
+                r#"
+                insert into toss_run_areas (run_id, area, messages, dupes, new)
+                values (:id, :area, :messages, :dupes, :new)
+                "#,
+                named_params! {
+                    ":id": id,
+                    ":area": area,
+                    ":messages": a.messages,
+                    ":dupes": a.dupes,
+                    ":new": a.new,
+                },
+            )?;
+        }
+
+        tx.commit()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RunLog;
+    use crate::core::Area;
+    use crate::tosser::Stats;
+    use std::path::Path;
+
+    #[test]
+    fn record_runs() {
+        let log = RunLog::open(Path::new(":memory:")).unwrap();
+
+        let mut stats = Stats::start();
+        stats.packets = 1;
+        stats.bytes = 1000;
+        stats.area(&Area::Echomail("TEST.ECHO".to_string())).messages = 2;
+        stats.area(&Area::Netmail).dupes = 1;
+
+        log.record(&stats).unwrap();
+        log.record(&stats).unwrap();
+
+        let (runs, messages, dupes): (i64, i64, i64) = log
+            .conn
+            .query_row("select count(*), sum(messages), sum(dupes) from toss_runs", [], |r| {
+                Ok((r.get(0)?, r.get(1)?, r.get(2)?))
+            })
+            .unwrap();
+        assert_eq!((runs, messages, dupes), (2, 4, 2));
+
+        let areas: i64 = log
+            .conn
+            .query_row("select count(*) from toss_run_areas", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(areas, 4);
+    }
+}
@@ -0,0 +1,124 @@
+use chrono::{SecondsFormat, Utc};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::core::Area;
+
+/// What a toss has done, see [`super::toss`]
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    /// RFC 3339, UTC
+    pub started: String,
+    pub duration_ms: u64,
+    pub packets: usize,
+    pub bundles: usize,
+    /// Size of the packets and bundles
+    pub bytes: u64,
+    /// By echo tag in upper case, or `netmail`
+    pub areas: BTreeMap<String, AreaStats>,
+    /// Messages which went to quarantine
+    pub quarantined: usize,
+    /// Files moved to bad mail
+    pub bad_files: usize,
+    /// Files left in inbound for a later run
+    pub left: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AreaStats {
+    /// Messages stored
+    pub messages: usize,
+    pub dupes: usize,
+    /// The message base has been created by this toss
+    pub new: bool,
+}
+
+impl Stats {
+    pub fn start() -> Self {
+        Self {
+            started: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ..Default::default()
+        }
+    }
+
+    pub fn messages(&self) -> usize {
+        self.areas.values().map(|a| a.messages).sum()
+    }
+
+    pub fn dupes(&self) -> usize {
+        self.areas.values().map(|a| a.dupes).sum()
+    }
+
+    pub fn new_areas(&self) -> impl Iterator<Item = &str> {
+        self.areas.iter().filter(|(_, a)| a.new).map(|(name, _)| name.as_str())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub(crate) fn area(&mut self, area: &Area) -> &mut AreaStats {
+        let name = match area {
+            Area::Netmail => "netmail".to_string(),
+            Area::Echomail(name) => name.to_ascii_uppercase(),
+        };
+
+        self.areas.entry(name).or_default()
+    }
+}
+
+/// Summary table
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<40} {:>8} {:>8}", "Area", "Messages", "Dupes")?;
+
+        for (name, a) in &self.areas {
+            let name = if a.new { format!("{name} (new)") } else { name.clone() };
+            writeln!(f, "{:<40} {:>8} {:>8}", name, a.messages, a.dupes)?;
+        }
+
+        writeln!(f, "{:<40} {:>8} {:>8}", "Total", self.messages(), self.dupes())?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{} packet(s), {} bundle(s), {} bytes in {:.2} s",
+            self.packets,
+            self.bundles,
+            self.bytes,
+            self.duration_ms as f64 / 1000.0
+        )?;
+        write!(
+            f,
+            "{} quarantined message(s), {} bad file(s), {} file(s) left in inbound",
+            self.quarantined, self.bad_files, self.left
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Stats;
+    use crate::core::Area;
+
+    #[test]
+    fn count_by_area() {
+        let mut stats = Stats::start();
+
+        stats.area(&Area::Echomail("test.echo".to_string())).messages += 2;
+        stats.area(&Area::Echomail("TEST.ECHO".to_string())).dupes += 1;
+        stats.area(&Area::Netmail).messages += 1;
+        stats.area(&Area::Netmail).new = true;
+
+        assert_eq!(stats.areas.len(), 2);
+        assert_eq!(stats.messages(), 3);
+        assert_eq!(stats.dupes(), 1);
+        assert_eq!(stats.new_areas().collect::<Vec<_>>(), ["netmail"]);
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["areas"]["TEST.ECHO"]["messages"], 2);
+        assert_eq!(json["areas"]["netmail"]["new"], true);
+
+        assert!(stats.to_string().contains("netmail (new)"));
+    }
+}
@@ -0,0 +1,133 @@
+use inotify::{Inotify, WatchMask};
+use log::{error, info};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cfg::Config;
+use crate::error::Result;
+use crate::lock::Lock;
+use crate::tosser::{self, Session};
+
+/// Files keep arriving for a while after the first one, a toss waits for a pause this long
+const DEBOUNCE: Duration = Duration::from_secs(2);
+/// How often signals and inotify are looked at
+const POLL: Duration = Duration::from_millis(200);
+/// Pause after a failed toss, doubled with each failure in a row up to `MAX_BACKOFF`
+const BACKOFF: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Tosses whatever is in the inbounds, then again whenever files arrive there. Message bases stay open
+/// between tosses. SIGHUP reloads the config from `config_path`, SIGTERM and SIGINT stop
+/// after the file being tossed. A failed toss is tried again after a pause, only failures to set
+/// up the config, the lock or inotify end the watch.
+pub fn watch(config_path: &Path) -> Result<()> {
+    let mut config = Config::new(config_path)?;
+    let mut session = Session::default();
+    let reload = Arc::new(AtomicBool::new(false));
+
+    signal_hook::flag::register(SIGHUP, Arc::clone(&reload))?;
+    signal_hook::flag::register(SIGTERM, Arc::clone(&session.stop))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&session.stop))?;
+
+    let mut inotify = watch_inbound(&config)?;
+    let mut buffer = [0u8; 4096];
+    let mut due = Some(Instant::now());
+    let mut backoff = BACKOFF;
+    // arriving files don't cut the pause after a failure short
+    let mut not_before = Instant::now();
+
+    while !session.stop.load(Ordering::Relaxed) {
+        if reload.swap(false, Ordering::Relaxed) {
+            match Config::new(config_path) {
+                Ok(c) => {
+                    config = c;
+
+                    if let Err(e) = crate::logging::init(&config) {
+                        error!("Log settings are not reloaded, reason: {e}");
+                    }
+
+                    info!("Config reloaded");
+                    session.close();
+                    inotify = watch_inbound(&config)?;
+                    backoff = BACKOFF;
+                    not_before = Instant::now();
+                    due = Some(not_before);
+                }
+                Err(e) => error!("Config is not reloaded, reason: {e}"),
+            }
+        }
+
+        match inotify.read_events(&mut buffer) {
+            Ok(mut events) => {
+                if events.next().is_some() {
+                    due = Some((Instant::now() + DEBOUNCE).max(not_before));
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if due.is_some_and(|d| d <= Instant::now()) {
+            // files which are still being written get another try once they have settled
+            due = match toss(&config, &mut session)? {
+                Ok(left) => {
+                    backoff = BACKOFF;
+                    Some(Instant::now() + settle_time(&config).max(DEBOUNCE)).filter(|_| left > 0)
+                }
+                Err(e) => {
+                    error!("Toss failed, next try in {} s, reason: {e}", backoff.as_secs());
+                    session.close();
+                    not_before = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    Some(not_before)
+                }
+            };
+        }
+
+        thread::sleep(POLL);
+    }
+
+    info!("Stopped");
+
+    Ok(())
+}
+
+fn watch_inbound(config: &Config) -> Result<Inotify> {
+    let inotify = Inotify::init()?;
+
+    for path in config.inbounds().iter().filter_map(|i| i.path.as_ref()) {
+        inotify
+            .watches()
+            .add(path, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+    }
+
+    Ok(inotify)
+}
+
+/// Longest settle time of the inbounds
+fn settle_time(config: &Config) -> Duration {
+    config
+        .inbounds()
+        .iter()
+        .map(|i| i.settle_time())
+        .max()
+        .unwrap_or_default()
+}
+
+/// Other runs wait for the lock as they'd do for a toss from cron, a lock held by another run
+/// leaves the files for later. The outer error is a lock which can't be set up, the inner one
+/// a failed toss. Returns how many files have been left in inbound.
+fn toss(config: &Config, session: &mut Session) -> Result<Result<usize>> {
+    let _lock = match Lock::for_config(config) {
+        Ok(lock) => lock,
+        Err(crate::Error::Locked(_)) => return Ok(Ok(1)),
+        Err(e) => return Err(e),
+    };
+
+    Ok(tosser::toss_in(config, session).map(|stats| stats.left))
+}